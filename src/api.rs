@@ -0,0 +1,135 @@
+use std::env;
+use std::sync::Arc;
+
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::balance;
+use crate::config::GameConfig;
+use crate::matchmaking::Matchmaker;
+use crate::server::{Drain, GameServer, DEFAULT_DRAIN_SECS};
+use crate::storage::{SharedStorage, StorageError};
+use crate::validation::{InvalidUsername, UsernameFilter};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ClaimUsername {
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct AdminAuth {
+    pub password: String,
+}
+
+impl AdminAuth {
+    fn is_valid(&self) -> bool {
+        env::var("LD47_PASSWORD").map(|password| password == self.password).unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DrainOptions {
+    pub secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct Draining {
+    pub running_games: usize,
+}
+
+#[derive(Deserialize)]
+pub struct Pagination {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl Pagination {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+    }
+
+    fn offset(&self) -> usize {
+        self.offset.unwrap_or(0)
+    }
+}
+
+fn error_response(err: StorageError) -> HttpResponse {
+    match err {
+        StorageError::UsernameTaken => HttpResponse::Conflict().body(err.to_string()),
+        StorageError::NotFound => HttpResponse::NotFound().body(err.to_string()),
+        StorageError::Database(_) => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+pub async fn claim_username(
+    storage: web::Data<SharedStorage>,
+    username_filter: web::Data<Arc<UsernameFilter>>,
+    body: web::Json<ClaimUsername>,
+) -> HttpResponse {
+    let username = match username_filter.validate(&body.username) {
+        Ok(username) => username,
+        Err(err) => return HttpResponse::BadRequest().json(InvalidUsername::from(err)),
+    };
+
+    match storage.lock().unwrap().claim_username(&username) {
+        Ok(account) => HttpResponse::Ok().json(serde_json::json!({
+            "id": account.id,
+            "username": account.username,
+            "token": account.token,
+        })),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn match_history(
+    storage: web::Data<SharedStorage>, username: web::Path<String>, page: web::Query<Pagination>,
+) -> HttpResponse {
+    match storage.lock().unwrap().match_history(&username, page.limit(), page.offset()) {
+        Ok(matches) => HttpResponse::Ok().json(matches),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn leaderboard(
+    storage: web::Data<SharedStorage>, page: web::Query<Pagination>,
+) -> HttpResponse {
+    match storage.lock().unwrap().leaderboard(page.limit(), page.offset()) {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn card_usage(storage: web::Data<SharedStorage>, auth: web::Query<AdminAuth>) -> HttpResponse {
+    if !auth.is_valid() {
+        return HttpResponse::Unauthorized().body("Wrong password");
+    }
+
+    match storage.lock().unwrap().card_usage() {
+        Ok(usage) => HttpResponse::Ok().json(balance::card_reports(&GameConfig::default().card_weights, &usage)),
+        Err(err) => error_response(err),
+    }
+}
+
+/// Starts a graceful shutdown, the process exits once the running games are over or `secs` have passed
+pub async fn drain(
+    server: web::Data<Addr<GameServer>>, matchmaker: web::Data<Addr<Matchmaker>>,
+    auth: web::Query<AdminAuth>, options: web::Query<DrainOptions>,
+) -> HttpResponse {
+    if !auth.is_valid() {
+        return HttpResponse::Unauthorized().body("Wrong password");
+    }
+
+    let secs = options.secs.unwrap_or(DEFAULT_DRAIN_SECS);
+    matchmaker.do_send(Drain { secs: secs });
+    match server.send(Drain { secs: secs }).await {
+        Ok(running_games) => HttpResponse::Ok().json(Draining { running_games: running_games }),
+        Err(_) => HttpResponse::InternalServerError().body("Game server is unavailable"),
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use actix::SpawnHandle;
+use rand::prelude::*;
+use rand::distributions::WeightedIndex;
+use serde::Serialize;
+
+use crate::cards::CardCatalogue;
+use crate::config::{GameConfig, GameMode, QueueType};
+use crate::map::MapDefinition;
+use crate::models::{CardUsage, Cosmetics, Game, GameOver, Mutation, NewPlayer, Player};
+use crate::rating::DEFAULT_RATING;
+use crate::server::{GameServer, BOARD_SIZE};
+use crate::simulator::Simulator;
+
+/// Games still running after this many turns end with every survivor sharing first place
+const MAX_TURNS: usize = 200;
+/// Suggested weights stay within this factor of the current ones
+const MAX_WEIGHT_CHANGE: f64 = 2.0;
+/// Cards picked fewer times than this keep their weight, a handful of games says little about them
+const MIN_PICKS: usize = 30;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CardReport {
+    pub card: u8,
+    pub offered: usize,
+    pub picked: usize,
+    pub winning_picks: usize,
+    /// How often the card was picked when offered
+    pub pick_rate: Option<f64>,
+    /// How often a pick of the card was made by a winner
+    pub win_rate: Option<f64>,
+    pub weight: f64,
+    pub suggested_weight: f64,
+}
+
+/// Adds up the usage of several games per card
+pub fn merge_usage(usage: &mut Vec<CardUsage>, more: Vec<CardUsage>) {
+    for card_usage in more {
+        match usage.iter_mut().find(|u| u.card == card_usage.card) {
+            Some(total) => {
+                total.offered += card_usage.offered;
+                total.picked += card_usage.picked;
+                total.winning_picks += card_usage.winning_picks;
+            },
+            None => usage.push(card_usage),
+        }
+    }
+    usage.sort_by_key(|card_usage| card_usage.card);
+}
+
+/// Deals cards that win more often than average less often and the other way round
+pub fn suggest_weights(weights: &[f64], usage: &[CardUsage]) -> Vec<f64> {
+    let total_picks: usize = usage.iter().map(|u| u.picked).sum();
+    let total_wins: usize = usage.iter().map(|u| u.winning_picks).sum();
+    if total_picks == 0 {
+        return weights.to_vec();
+    }
+    let baseline = total_wins as f64 / total_picks as f64;
+
+    weights.iter().enumerate().map(|(card, weight)| {
+        match usage.iter().find(|u| u.card as usize == card) {
+            Some(u) if u.picked >= MIN_PICKS && *weight > 0f64 => {
+                let win_rate = u.winning_picks as f64 / u.picked as f64;
+                let factor = if win_rate > 0f64 { baseline / win_rate } else { MAX_WEIGHT_CHANGE };
+                weight * factor.clamp(1f64 / MAX_WEIGHT_CHANGE, MAX_WEIGHT_CHANGE)
+            },
+            _ => *weight,
+        }
+    }).collect()
+}
+
+pub fn card_reports(weights: &[f64], usage: &[CardUsage]) -> Vec<CardReport> {
+    let suggested = suggest_weights(weights, usage);
+    let by_card: HashMap<u8, &CardUsage> = usage.iter().map(|u| (u.card, u)).collect();
+
+    weights.iter().enumerate().map(|(card, weight)| {
+        let card_usage = by_card.get(&(card as u8)).cloned().cloned().unwrap_or_default();
+        CardReport {
+            card: card as u8,
+            offered: card_usage.offered,
+            picked: card_usage.picked,
+            winning_picks: card_usage.winning_picks,
+            pick_rate: if card_usage.offered > 0 { Some(card_usage.picked as f64 / card_usage.offered as f64) } else { None },
+            win_rate: if card_usage.picked > 0 { Some(card_usage.winning_picks as f64 / card_usage.picked as f64) } else { None },
+            weight: *weight,
+            suggested_weight: suggested[card],
+        }
+    }).collect()
+}
+
+/// Plays a game between bots on the simulator alone, the bots program random cards out of their options
+pub fn play_bot_game<R: Rng>(gc: &GameConfig, map: &MapDefinition, mode: GameMode, players: usize, rng: &mut R) -> GameOver {
+    let card_dists: Vec<_> = (0 .. gc.characters.len())
+        .map(|character_type| WeightedIndex::new(gc.character_card_weights(character_type as u8)).unwrap())
+        .collect();
+    let available_pos = GameServer::spawn_positions(map, players, rng);
+    let mut game = Game::new(mode, QueueType::Casual, gc.lobby.clone(), vec![map.clone()], available_pos, SpawnHandle::default());
+    game.game_started = true;
+
+    for id in 0 .. players.min(game.available_pos.len()) {
+        let pos = game.available_pos.remove(0);
+        let team = match mode {
+            GameMode::Teams => Some((id % gc.team_count as usize) as u8),
+            GameMode::FreeForAll | GameMode::Loop => None,
+        };
+        let character_type = rng.gen_range(0, gc.characters.len()) as u8;
+        let health = gc.character(character_type).health;
+        let cosmetics = Cosmetics { color: id as u8, ..Cosmetics::default() };
+        game.players.push(Player::new(NewPlayer {
+            id: id,
+            game_id: 0,
+            username: format!("Bot {}", id),
+            character_type: character_type,
+            team: team,
+            pos: pos,
+            is_ai: true,
+            health: health,
+            cosmetics: cosmetics,
+            account_id: None,
+            rating: DEFAULT_RATING,
+            addr: None,
+        }, rng));
+    }
+
+    while game.remaining_sides() > 1 && game.turn_index < MAX_TURNS {
+        for player in game.players.iter_mut().filter(|p| p.active) {
+            let card_dist = &card_dists[player.character_type as usize];
+            let mut options = Vec::new();
+            for _ in 0 .. gc.options_per_turn.max(gc.program_slots) {
+                options.push(card_dist.sample(rng) as u8);
+            }
+            for card in options.iter() {
+                *player.stats.cards_offered.entry(*card).or_insert(0) += 1;
+            }
+
+            options.shuffle(rng);
+            player.program = options.into_iter().take(gc.program_slots).enumerate().map(|(slot, card)| {
+                Some(Mutation { user_id: player.id, card_type: card, card_location: slot as u8 })
+            }).collect();
+            for mutation in player.program.iter().flatten() {
+                *player.stats.cards_played.entry(mutation.card_type).or_insert(0) += 1;
+            }
+        }
+
+        let order = Simulator::resolution_order(&game.players, gc);
+        Simulator::resolve_turn(&mut game, &order, gc, rng);
+
+        game.advance_turn(gc);
+    }
+
+    GameOver::from(0, &game)
+}
+
+/// `ld47-actix suggest-weights [games]`, plays bot games with the card catalogue and prints new card weights
+pub fn suggest_weights_command(games: usize, maps: &[MapDefinition]) {
+    let catalogue = CardCatalogue::from_env();
+    if catalogue.cards.is_empty() {
+        println!("No card catalogue to simulate, set LD47_CARDS");
+        return;
+    }
+
+    let gc = GameConfig {
+        cards: catalogue.cards,
+        actions: catalogue.actions,
+        ..GameConfig::default()
+    };
+    let open_map = MapDefinition::open(BOARD_SIZE);
+    let mut rng = rand::thread_rng();
+    let mut usage = Vec::new();
+
+    for n in 0 .. games {
+        let map = maps.choose(&mut rng).unwrap_or(&open_map);
+        let game_over = play_bot_game(&gc, map, GameMode::FreeForAll, gc.lobby.max_players, &mut rng);
+        merge_usage(&mut usage, CardUsage::from_game(&game_over, true));
+
+        if (n + 1) % 100 == 0 {
+            println!("Played {} of {} games", n + 1, games);
+        }
+    }
+
+    println!("card\toffered\tpicked\twin rate\tweight\tsuggested");
+    let reports = card_reports(&gc.card_weights, &usage);
+    for report in reports.iter() {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{:.2}",
+            report.card,
+            report.offered,
+            report.picked,
+            report.win_rate.map(|rate| format!("{:.3}", rate)).unwrap_or_else(|| String::from("-")),
+            report.weight,
+            report.suggested_weight,
+        );
+    }
+
+    let suggested: Vec<_> = reports.iter().map(|report| (report.suggested_weight * 100f64).round() / 100f64).collect();
+    if let Ok(json_string) = serde_json::to_string(&suggested) {
+        println!("{}", json_string);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(card: u8, picked: usize, winning_picks: usize) -> CardUsage {
+        CardUsage { card: card, offered: picked, picked: picked, winning_picks: winning_picks }
+    }
+
+    #[test]
+    fn cards_that_win_less_are_dealt_more() {
+        let weights = suggest_weights(&[10f64, 10f64], &[usage(0, 100, 10), usage(1, 100, 30)]);
+        assert!(weights[0] > 10f64);
+        assert!(weights[1] < 10f64);
+    }
+
+    #[test]
+    fn weights_change_at_most_by_the_limit() {
+        let weights = suggest_weights(&[10f64, 10f64, 10f64], &[usage(0, 100, 0), usage(1, 100, 1), usage(2, 100, 99)]);
+        assert_eq!(weights[0], 10f64 * MAX_WEIGHT_CHANGE);
+        assert_eq!(weights[1], 10f64 * MAX_WEIGHT_CHANGE);
+        assert_eq!(weights[2], 10f64 / MAX_WEIGHT_CHANGE);
+    }
+
+    #[test]
+    fn rarely_picked_cards_keep_their_weight() {
+        let weights = suggest_weights(&[10f64, 10f64], &[usage(0, MIN_PICKS - 1, 0), usage(1, 100, 50)]);
+        assert_eq!(weights[0], 10f64);
+    }
+
+    #[test]
+    fn bot_games_finish() {
+        let catalogue = CardCatalogue::load(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("cards.json")).unwrap();
+        let gc = GameConfig {
+            cards: catalogue.cards,
+            actions: catalogue.actions,
+            ..GameConfig::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        for mode in [GameMode::FreeForAll, GameMode::Teams, GameMode::Loop].iter() {
+            let game_over = play_bot_game(&gc, &MapDefinition::open(BOARD_SIZE), *mode, 6, &mut rng);
+            assert_eq!(game_over.players.len(), 6);
+            assert!(game_over.players.iter().any(|p| p.placement == 1));
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::Serialize;
+
+use ld47_actix::balance::{self, CardReport};
+use ld47_actix::cards::CardCatalogue;
+use ld47_actix::config::{GameConfig, GameMode};
+use ld47_actix::map::MapDefinition;
+use ld47_actix::models::{CardUsage, GameOver};
+use ld47_actix::server::{BOARD_SIZE, MAX_PLAYERS};
+
+const USAGE: &str = "Usage: simulate [--seeds START..END] [--mode MODE] [--map NAME] [--players N] [--threads N] [--out PREFIX]
+
+Plays bot-only games on the simulator, one per seed, and writes PREFIX.json with aggregate
+stats and PREFIX.csv with a row per game. Cards come from LD47_CARDS and maps from LD47_MAPS_DIR.";
+
+struct Options {
+    seeds: (u64, u64),
+    mode: GameMode,
+    map: String,
+    players: usize,
+    threads: usize,
+    out: String,
+}
+
+impl Options {
+    fn parse() -> Result<Options, String> {
+        let mut options = Options {
+            seeds: (0, 100),
+            mode: GameMode::FreeForAll,
+            map: String::from("Open"),
+            players: MAX_PLAYERS,
+            threads: 4,
+            out: String::from("simulation"),
+        };
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--seeds" => {
+                    let mut range = value.splitn(2, "..").map(|seed| seed.parse::<u64>());
+                    options.seeds = match (range.next(), range.next()) {
+                        (Some(Ok(start)), Some(Ok(end))) if start < end => (start, end),
+                        _ => return Err(format!("Invalid seed range {}", value)),
+                    };
+                },
+                "--mode" => {
+                    options.mode = serde_json::from_str(&format!("\"{}\"", value))
+                        .map_err(|_| format!("Unknown mode {}", value))?;
+                },
+                "--map" => options.map = value,
+                "--players" => options.players = value.parse().map_err(|_| format!("Invalid player count {}", value))?,
+                "--threads" => options.threads = value.parse().map_err(|_| format!("Invalid thread count {}", value))?,
+                "--out" => options.out = value,
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// One finished game, a row of the CSV
+struct GameRecord {
+    seed: u64,
+    game_over: GameOver,
+}
+
+impl GameRecord {
+    /// Nobody won when the turn limit left several sides standing or the last sides died together
+    fn is_draw(&self) -> bool {
+        self.game_over.winner_id.is_none() && self.game_over.winning_team.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct CharacterStats {
+    character_type: u8,
+    games: usize,
+    wins: usize,
+    win_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Summary {
+    games: usize,
+    mode: GameMode,
+    map: String,
+    players: usize,
+    /// Games without a winner, see `GameRecord::is_draw`
+    draws: usize,
+    mean_turns: f64,
+    min_turns: usize,
+    max_turns: usize,
+    characters: Vec<CharacterStats>,
+    cards: Vec<CardReport>,
+}
+
+fn summarize(options: &Options, gc: &GameConfig, records: &[GameRecord]) -> Summary {
+    let turns: Vec<_> = records.iter().map(|record| record.game_over.turns).collect();
+    let mut characters: Vec<CharacterStats> = Vec::new();
+    let mut usage = Vec::new();
+
+    for record in records.iter() {
+        for result in record.game_over.players.iter() {
+            let index = match characters.iter().position(|c| c.character_type == result.character_type) {
+                Some(index) => index,
+                None => {
+                    characters.push(CharacterStats { character_type: result.character_type, ..CharacterStats::default() });
+                    characters.len() - 1
+                },
+            };
+            characters[index].games += 1;
+            if result.placement == 1 && !record.is_draw() {
+                characters[index].wins += 1;
+            }
+        }
+        balance::merge_usage(&mut usage, CardUsage::from_game(&record.game_over, true));
+    }
+
+    characters.sort_by_key(|c| c.character_type);
+    for character in characters.iter_mut() {
+        character.win_rate = character.wins as f64 / character.games as f64;
+    }
+
+    Summary {
+        games: records.len(),
+        mode: options.mode,
+        map: options.map.clone(),
+        players: options.players,
+        draws: records.iter().filter(|record| record.is_draw()).count(),
+        mean_turns: turns.iter().sum::<usize>() as f64 / turns.len().max(1) as f64,
+        min_turns: turns.iter().cloned().min().unwrap_or(0),
+        max_turns: turns.iter().cloned().max().unwrap_or(0),
+        characters: characters,
+        cards: balance::card_reports(&gc.card_weights, &usage),
+    }
+}
+
+fn to_csv(records: &[GameRecord]) -> String {
+    let mut csv = String::from("seed,turns,winners,winner_characters,winning_team\n");
+    for record in records.iter() {
+        let winners: Vec<_> = record.game_over.players.iter().filter(|p| p.placement == 1 && !record.is_draw()).collect();
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            record.seed,
+            record.game_over.turns,
+            winners.iter().map(|p| p.user_id.to_string()).collect::<Vec<_>>().join(" "),
+            winners.iter().map(|p| p.character_type.to_string()).collect::<Vec<_>>().join(" "),
+            record.game_over.winning_team.map(|team| team.to_string()).unwrap_or_default(),
+        ));
+    }
+
+    csv
+}
+
+fn main() {
+    let options = match Options::parse() {
+        Ok(options) => options,
+        Err(err) => {
+            println!("{}\n\n{}", err, USAGE);
+            return;
+        },
+    };
+
+    let catalogue = CardCatalogue::from_env();
+    if catalogue.cards.is_empty() {
+        println!("No card catalogue to simulate, set LD47_CARDS");
+        return;
+    }
+    let gc = Arc::new(GameConfig {
+        cards: catalogue.cards,
+        actions: catalogue.actions,
+        ..GameConfig::default()
+    });
+
+    let maps_dir = env::var("LD47_MAPS_DIR").unwrap_or_else(|_| String::from("maps"));
+    let map = match MapDefinition::load_dir(Path::new(&maps_dir), MAX_PLAYERS).into_iter().find(|map| map.name == options.map) {
+        Some(map) => map,
+        None if options.map == "Open" => MapDefinition::open(BOARD_SIZE),
+        None => {
+            println!("Unknown map {}", options.map);
+            return;
+        },
+    };
+    let map = Arc::new(map);
+
+    // Seeds are dealt round robin so every thread gets a similar share
+    let (start, end) = options.seeds;
+    let threads = options.threads.max(1);
+    let handles: Vec<_> = (0 .. threads as u64).map(|thread_index| {
+        let gc = gc.clone();
+        let map = map.clone();
+        let mode = options.mode;
+        let players = options.players;
+
+        thread::spawn(move || {
+            (start + thread_index .. end).step_by(threads).map(|seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                GameRecord {
+                    seed: seed,
+                    game_over: balance::play_bot_game(&gc, &map, mode, players, &mut rng),
+                }
+            }).collect::<Vec<_>>()
+        })
+    }).collect();
+
+    let mut records: Vec<GameRecord> = handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect();
+    records.sort_by_key(|record| record.seed);
+
+    let summary = summarize(&options, &gc, &records);
+    let json_path = format!("{}.json", options.out);
+    let csv_path = format!("{}.csv", options.out);
+    match serde_json::to_string_pretty(&summary) {
+        Ok(json_string) => {
+            if let Err(err) = fs::write(&json_path, json_string) {
+                println!("Failed to write {}: {}", json_path, err);
+            }
+        },
+        Err(err) => println!("Failed to serialize summary: {}", err),
+    }
+    if let Err(err) = fs::write(&csv_path, to_csv(&records)) {
+        println!("Failed to write {}: {}", csv_path, err);
+    }
+
+    println!(
+        "Played {} games, {} draws, {:.1} turns on average, wrote {} and {}",
+        summary.games, summary.draws, summary.mean_turns, json_path, csv_path
+    );
+}
@@ -0,0 +1,101 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::{Action, Card};
+
+/// Card types are sent as a `u8`
+const MAX_CARDS: usize = 256;
+
+/// Primitive actions and the cards built out of them
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CardCatalogue {
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    #[serde(default)]
+    pub cards: Vec<Card>,
+}
+
+#[derive(Debug)]
+pub enum CatalogueError {
+    Io(String),
+    Parse(String),
+    TooManyCards { count: usize },
+    UnknownAction { card: usize, action: usize },
+}
+
+impl fmt::Display for CatalogueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatalogueError::Io(err) => write!(f, "Failed to read card catalogue: {}", err),
+            CatalogueError::Parse(err) => write!(f, "Failed to parse card catalogue: {}", err),
+            CatalogueError::TooManyCards { count } => write!(f, "Catalogue has {} cards, at most {} are allowed", count, MAX_CARDS),
+            CatalogueError::UnknownAction { card, action } => write!(f, "Card {} uses unknown action {}", card, action),
+        }
+    }
+}
+
+impl CardCatalogue {
+    pub fn load(path: &Path) -> Result<Self, CatalogueError> {
+        let contents = fs::read_to_string(path).map_err(|err| CatalogueError::Io(err.to_string()))?;
+        serde_json::from_str(&contents).map_err(|err| CatalogueError::Parse(err.to_string()))
+    }
+
+    /// Loads the catalogue in `LD47_CARDS` (`cards.json` by default), an empty one if there is none
+    pub fn from_env() -> Self {
+        let path = env::var("LD47_CARDS").unwrap_or_else(|_| String::from("cards.json"));
+        if !Path::new(&path).exists() {
+            return CardCatalogue::default();
+        }
+
+        match CardCatalogue::load(Path::new(&path)).and_then(|catalogue| catalogue.validate().map(|_| catalogue)) {
+            Ok(catalogue) => catalogue,
+            Err(err) => {
+                println!("Ignoring card catalogue {}: {}", path, err);
+                CardCatalogue::default()
+            },
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), CatalogueError> {
+        if self.cards.len() > MAX_CARDS {
+            return Err(CatalogueError::TooManyCards { count: self.cards.len() });
+        }
+
+        for (card_id, card) in self.cards.iter().enumerate() {
+            if let Some(action) = card.actions.iter().find(|action| **action >= self.actions.len()) {
+                return Err(CatalogueError::UnknownAction { card: card_id, action: *action });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+
+    #[test]
+    fn the_shipped_catalogue_defines_every_card() {
+        let catalogue = CardCatalogue::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("cards.json")).unwrap();
+        assert!(catalogue.validate().is_ok());
+        assert_eq!(catalogue.cards.len(), GameConfig::default().card_weights.len());
+    }
+
+    #[test]
+    fn cards_cant_use_missing_actions() {
+        let catalogue: CardCatalogue = serde_json::from_str(r#"{
+            "actions": [{ "kind": "Move", "tiles": 1 }],
+            "cards": [{ "actions": [0] }, { "actions": [0, 1] }]
+        }"#).unwrap();
+        match catalogue.validate() {
+            Err(CatalogueError::UnknownAction { card: 1, action: 1 }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
@@ -1,24 +1,316 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameMode {
+    FreeForAll,
+    Teams,
+    /// Free for all on a board that shrinks along a ring every few turns
+    Loop,
+}
+
+/// Casual games may be padded with bots and never change ratings, ranked games are humans only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum QueueType {
+    Casual,
+    Ranked,
+}
+
+impl QueueType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueueType::Casual => "Casual",
+            QueueType::Ranked => "Ranked",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BotDifficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl BotDifficulty {
+    /// How many cards a bot draws before playing the rarest one, rare cards being the strong ones
+    pub fn draws(&self) -> usize {
+        match self {
+            BotDifficulty::Easy => 1,
+            BotDifficulty::Normal => 2,
+            BotDifficulty::Hard => 4,
+        }
+    }
+}
+
+/// Start rules of a lobby
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LobbySettings {
+    /// Bots always pad the game up to this many players
+    pub min_players: usize,
+    pub max_players: usize,
+    /// The countdown is extended until this many humans are in the lobby
+    pub min_humans: usize,
+    /// Pad the game with bots up to `max_players` instead of `min_players`
+    pub fill_with_bots: bool,
+    pub countdown_secs: u64,
+    /// The countdown is cut down to this once every human is ready
+    pub ready_countdown_secs: u64,
+    pub bot_difficulty: BotDifficulty,
+    /// `None` samples the options from the card weights every turn
+    #[serde(default)]
+    pub deck: Option<DeckSettings>,
+}
+
+/// Deals each player their own shuffled deck instead of sampling every turn's options independently
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeckSettings {
+    pub hand_size: usize,
+    /// Copies of the most common card in a deck, rarer cards get proportionally fewer (at least one)
+    pub max_copies: usize,
+}
+
+/// Looks players can pick, they don't change how a character plays
+#[derive(Debug, Clone)]
+pub struct CosmeticSettings {
+    /// Player colors as hex strings, a color is its index and no two players in a game share one
+    pub palette: Vec<String>,
+    pub hats: u8,
+    pub skins: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PassiveAbility {
+    /// Takes no damage from being pushed into walls
+    Sturdy,
+    /// Shots deal one more damage
+    Sharpshooter,
+    /// Can't be disoriented
+    Steady,
+}
+
+/// A character type is its index in `GameConfig::characters`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterDefinition {
+    pub name: String,
+    pub health: u8,
+    /// Tiles added to (or taken off) every move, a move never drops below one tile
+    pub move_modifier: i8,
+    pub passive: Option<PassiveAbility>,
+    /// Card type to the weight the character is dealt it with instead of `GameConfig::card_weights`
+    pub card_weights: HashMap<u8, f64>,
+}
+
 pub struct Animation {
     pub move_distance: u16,
     pub rotation: u8,
 }
 
+/// Building block of a card, orientations are 0 up, 1 right, 2 down and 3 left
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Action {
+    /// Walks forward, or backwards without turning around for a negative number of tiles
+    Move { tiles: i8 },
+    /// Turns clockwise
+    Rotate { quarter_turns: u8 },
+    /// Shoves the player on the tile in front away
+    Push { tiles: u8 },
+    /// Hits the first player in the facing direction for the card's `dmg`, `None` reaches across the board
+    Shoot { range: Option<u16> },
+    /// Makes the first player in the facing direction move in random directions
+    Disorient { range: Option<u16>, turns: u8 },
+    /// The first player in the facing direction skips their cards
+    Stun { range: Option<u16>, turns: u8 },
+    /// Blocks all damage taken
+    Shield { turns: u8 },
+    Heal { amount: u8 },
+}
+
+/// A card type is its index in `GameConfig::cards`
+#[derive(Debug, Clone, Deserialize)]
 pub struct Card {
+    /// Whether players hit by the card are also disoriented for `GameConfig::disorient_turns`
+    #[serde(default)]
     pub disorient: bool,
+    #[serde(default)]
     pub dmg: u8,
+    /// Cards in the same program slot resolve highest priority first, ties go to the lower player id
+    #[serde(default)]
+    pub priority: u8,
+    /// Ids into `GameConfig::actions`, played in order
     pub actions: Vec<usize>
 }
 
 pub struct GameConfig {
-    pub cards: usize,
-    pub actions: usize,
+    /// How likely each card type is to be dealt, hand tuned (see `ld47-actix suggest-weights`)
+    pub card_weights: Vec<f64>,
+    /// Cards the server simulates, card types without an entry are left to the clients
+    pub cards: Vec<Card>,
+    pub actions: Vec<Action>,
+    pub characters: Vec<CharacterDefinition>,
+    pub disorient_turns: u8,
+    /// Damage taken by each player in a line that is pushed into a wall or the board edge
+    pub collision_damage: u8,
+    /// Cards a player can choose between each turn, never fewer than `program_slots`
+    pub options_per_turn: usize,
+    /// Ordered slots each player programs a card into every turn, `card_location` is the slot
+    pub program_slots: usize,
+    /// Has at least as many colors as a game has players
+    pub cosmetics: CosmeticSettings,
+    /// Prefer open lobbies whose players have a rating close to the joining player
+    pub prefer_similar_ratings: bool,
+    /// Emotes players can send, an emote id is an index into this list
+    pub emotes: Vec<String>,
+    pub dead_can_emote: bool,
+    /// Number of teams in `GameMode::Teams`
+    pub team_count: u8,
+    /// Whether players can damage their own team mates
+    pub friendly_fire: bool,
+    /// How often the ring contracts in `GameMode::Loop`
+    pub shrink_every_turns: usize,
+    /// The ring stops contracting once the playable area is this small
+    pub min_ring_size: (u16, u16),
+    /// Damage taken each turn outside the ring, `None` kills outright
+    pub ring_damage: Option<u8>,
+    /// Map names played in order for each mode, modes without an entry rotate through every loaded map
+    pub map_rotation: HashMap<GameMode, Vec<String>>,
+    /// How many maps from the rotation a lobby votes between, 1 turns voting off
+    pub map_vote_options: usize,
+    pub lobby: LobbySettings,
+    pub ranked_lobby: LobbySettings,
 }
 
 impl GameConfig {
     pub fn default() -> Self {
         GameConfig {
-            cards: 38,
-            actions: 0,
+            card_weights: vec![15f64,
+                15f64,
+                5f64,
+                10f64,
+                15f64,
+                15f64,
+                5f64,
+                0f64,
+                0f64,
+                3.6f64,
+                3.6f64,
+                3.6f64,
+                3.6f64,
+                3.6f64,
+                3.6f64,
+                3.6f64,
+                2.5f64,
+                2.5f64,
+                3.6f64,
+                3.6f64,
+                3.6f64,
+                3.6f64,
+                2.3f64,
+                2.3f64,
+                2.3f64,
+                2.3f64,
+                3.6f64,
+                3.6f64,
+                2.0f64,
+                2.0f64,
+                2.0f64,
+                2.0f64,
+                2.0f64,
+                1.2f64,
+                2.0f64,
+                2.0f64,
+                2.0f64,
+                1.2f64],
+            cards: Vec::new(),
+            actions: Vec::new(),
+            characters: vec![
+                CharacterDefinition {
+                    name: String::from("Scout"),
+                    health: 8,
+                    move_modifier: 1,
+                    passive: Some(PassiveAbility::Steady),
+                    card_weights: HashMap::new(),
+                },
+                CharacterDefinition {
+                    name: String::from("Brawler"),
+                    health: 12,
+                    move_modifier: 0,
+                    passive: Some(PassiveAbility::Sturdy),
+                    card_weights: HashMap::new(),
+                },
+                CharacterDefinition {
+                    name: String::from("Gunner"),
+                    health: 10,
+                    move_modifier: 0,
+                    passive: Some(PassiveAbility::Sharpshooter),
+                    card_weights: HashMap::new(),
+                },
+            ],
+            disorient_turns: 1,
+            collision_damage: 1,
+            options_per_turn: 3,
+            program_slots: 1,
+            cosmetics: CosmeticSettings {
+                palette: [
+                    "#e6194b", "#3cb44b", "#ffe119", "#4363d8", "#f58231",
+                    "#911eb4", "#46f0f0", "#f032e6", "#bcf60c", "#fabebe",
+                ].iter().map(|color| String::from(*color)).collect(),
+                hats: 6,
+                skins: 4,
+            },
+            prefer_similar_ratings: true,
+            emotes: vec!["wave", "laugh", "angry", "gg", "look_here", "danger"]
+                .into_iter().map(String::from).collect(),
+            dead_can_emote: false,
+            team_count: 2,
+            friendly_fire: false,
+            shrink_every_turns: 5,
+            min_ring_size: (4, 3),
+            ring_damage: Some(1),
+            map_rotation: HashMap::new(),
+            map_vote_options: 3,
+            lobby: LobbySettings {
+                min_players: 2,
+                max_players: 10,
+                min_humans: 1,
+                fill_with_bots: true,
+                countdown_secs: 30,
+                ready_countdown_secs: 5,
+                bot_difficulty: BotDifficulty::Normal,
+                deck: None,
+            },
+            ranked_lobby: LobbySettings {
+                min_players: 4,
+                max_players: 10,
+                min_humans: 4,
+                fill_with_bots: false,
+                countdown_secs: 20,
+                ready_countdown_secs: 3,
+                bot_difficulty: BotDifficulty::Normal,
+                deck: None,
+            },
+        }
+    }
+
+    /// Unknown character types fall back to the first character
+    pub fn character(&self, character_type: u8) -> &CharacterDefinition {
+        self.characters.get(character_type as usize).unwrap_or(&self.characters[0])
+    }
+
+    /// The card weights with the character's overrides applied
+    pub fn character_card_weights(&self, character_type: u8) -> Vec<f64> {
+        let overrides = &self.character(character_type).card_weights;
+        self.card_weights.iter().enumerate()
+            .map(|(card, weight)| *overrides.get(&(card as u8)).unwrap_or(weight))
+            .collect()
+    }
+
+    pub fn lobby_settings(&self, queue: QueueType) -> &LobbySettings {
+        match queue {
+            QueueType::Casual => &self.lobby,
+            QueueType::Ranked => &self.ranked_lobby,
         }
     }
 }
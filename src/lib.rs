@@ -0,0 +1,14 @@
+pub mod api;
+pub mod balance;
+pub mod cards;
+pub mod map;
+pub mod matchmaking;
+pub mod models;
+pub mod config;
+pub mod rating;
+pub mod replay;
+pub mod server;
+pub mod simulator;
+pub mod snapshot;
+pub mod storage;
+pub mod validation;
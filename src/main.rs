@@ -1,20 +1,24 @@
 use std::env;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 use actix::prelude::*;
+use actix::clock::{delay_for, Duration};
+use actix_web::dev::Server;
+use actix_web::rt::signal::ctrl_c;
+use actix_web::rt::signal::unix::{signal, SignalKind};
 use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
 
 use serde::{Deserialize};
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
 
-mod models;
-mod config;
-mod server;
-mod simulator;
+use ld47_actix::{api, balance, cards, config, map, matchmaking, models, server, storage, validation};
 
 struct GameWebSocket {
     id: usize,
     data: Addr<server::GameServer>,
+    matchmaker: Addr<matchmaking::Matchmaker>,
 }
 
 impl Actor for GameWebSocket {
@@ -42,6 +46,13 @@ pub enum MessageType {
         username: String,
         character_type: u8,
         color: Option<u8>,
+        hat: Option<u8>,
+        skin: Option<u8>,
+        token: Option<String>,
+        latency_ms: Option<u32>,
+        mode: Option<config::GameMode>,
+        queue: Option<config::QueueType>,
+        team: Option<u8>,
     },
     ChooseCard {
         card_number: u8,
@@ -51,17 +62,56 @@ pub enum MessageType {
         game_id: usize,
         user_id: usize,
     },
+    /// Comes back to a game after the server restarted, with the ids and key from `InitiateGame`
+    Rejoin {
+        game_id: usize,
+        user_id: usize,
+        pk: String,
+    },
     AnimationsDone {
         player_id: usize,
         pk: String,
         turn_id: usize,
         game_id: usize,
     },
-    PollPlayerDied {
-        other_id: usize,
-        self_id: usize,
+    /// Sent by older clients, deaths now come from the server's simulation of the turn so it is ignored
+    PollPlayerDied {},
+    Chat {
+        text: String,
+        user_id: usize,
+        pk: String,
+        game_id: usize,
+    },
+    Ready {
+        ready: bool,
+        user_id: usize,
+        pk: String,
+        game_id: usize,
+    },
+    HostCommand {
+        command: server::HostCommand,
+        user_id: usize,
+        pk: String,
+        game_id: usize,
+    },
+    VoteMap {
+        map_index: usize,
+        user_id: usize,
+        pk: String,
+        game_id: usize,
+    },
+    Emote {
+        emote_id: u8,
+        tile: Option<(u16, u16)>,
+        user_id: usize,
+        pk: String,
+        game_id: usize,
+    },
+    MutePlayer {
+        target_id: usize,
+        muted: bool,
+        user_id: usize,
         pk: String,
-        turn_id: usize,
         game_id: usize,
     },
     Prune {
@@ -69,7 +119,17 @@ pub enum MessageType {
     },
     FullClean {
         password: String,
-    }
+    },
+    Stats {
+        password: String,
+        game_id: Option<usize>,
+    },
+    AdminMute {
+        password: String,
+        game_id: usize,
+        player_id: usize,
+        muted: bool,
+    },
 }
 
 /// Handler for ws::Message message
@@ -83,18 +143,30 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameWebSocket {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
                 match serde_json::from_str(text.as_str()).unwrap() {
-                    MessageType::InitiateGame{username, character_type, color} => {
-                        self.data.send(server::Connect {
-                            username: username,
-                            character_type: character_type,
-                            addr: Some(ctx.address().recipient()),
-                            game_id: None, // Only for bot use
+                    MessageType::InitiateGame{username, character_type, color, hat, skin, token, latency_ms, mode, queue, team} => {
+                        self.matchmaker.send(matchmaking::JoinQueue {
+                            connect: server::Connect {
+                                username: username,
+                                character_type: character_type,
+                                team: team,
+                                cosmetics: server::CosmeticChoice {
+                                    color: color,
+                                    hat: hat,
+                                    skin: skin,
+                                },
+                                account_token: token,
+                                addr: Some(ctx.address().recipient()),
+                                game_id: None, // Assigned by the matchmaker
+                            },
+                            latency_ms: latency_ms,
+                            mode: mode.unwrap_or(config::GameMode::FreeForAll),
+                            queue: queue.unwrap_or(config::QueueType::Casual),
                         })
                         .into_actor(self)
-                        .then(|res, act, ctx| {
+                        .then(|res, _act, ctx| {
                             match res {
-                                Ok(res) => act.id = res as usize,
-                                // something is wrong with server
+                                Ok(_) => (),
+                                // something is wrong with the matchmaker
                                 _ => ctx.stop(),
                             }
                             fut::ready(())
@@ -125,17 +197,17 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameWebSocket {
                         })
                         .wait(ctx);
                     },
-                    MessageType::AnimationsDone {player_id, pk, game_id, turn_id} => {
-                        self.data.send(server::AnimationMessage {
-                            player_id: player_id,
+                    MessageType::Rejoin {game_id, user_id, pk} => {
+                        self.data.send(server::RejoinMessage {
+                            player_id: user_id,
                             pk: pk,
                             game_id: game_id,
-                            turn_id: turn_id,
+                            addr: ctx.address().recipient(),
                         })
                         .into_actor(self)
                         .then(|res, act, ctx| {
                             match res {
-                                Ok(res) => act.id = res as usize,
+                                Ok(res) => act.id = res,
                                 // something is wrong with server
                                 _ => ctx.stop(),
                             }
@@ -143,10 +215,9 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameWebSocket {
                         })
                         .wait(ctx);
                     },
-                    MessageType::PollPlayerDied {other_id, self_id, pk, game_id, turn_id} => {
-                        self.data.send(server::PollPlayerDeathMessage {
-                            other_id: other_id,
-                            self_id: self_id,
+                    MessageType::AnimationsDone {player_id, pk, game_id, turn_id} => {
+                        self.data.send(server::AnimationMessage {
+                            player_id: player_id,
                             pk: pk,
                             game_id: game_id,
                             turn_id: turn_id,
@@ -162,6 +233,56 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameWebSocket {
                         })
                         .wait(ctx);
                     },
+                    MessageType::Chat {text, user_id, pk, game_id} => {
+                        self.data.do_send(server::ChatMessage {
+                            text: text,
+                            player_id: user_id,
+                            pk: pk,
+                            game_id: game_id,
+                        });
+                    },
+                    MessageType::Ready {ready, user_id, pk, game_id} => {
+                        self.data.do_send(server::ReadyMessage {
+                            ready: ready,
+                            player_id: user_id,
+                            pk: pk,
+                            game_id: game_id,
+                        });
+                    },
+                    MessageType::HostCommand {command, user_id, pk, game_id} => {
+                        self.data.do_send(server::HostCommandMessage {
+                            command: command,
+                            player_id: user_id,
+                            pk: pk,
+                            game_id: game_id,
+                        });
+                    },
+                    MessageType::VoteMap {map_index, user_id, pk, game_id} => {
+                        self.data.do_send(server::MapVoteMessage {
+                            map_index: map_index,
+                            player_id: user_id,
+                            pk: pk,
+                            game_id: game_id,
+                        });
+                    },
+                    MessageType::Emote {emote_id, tile, user_id, pk, game_id} => {
+                        self.data.do_send(server::EmoteMessage {
+                            emote_id: emote_id,
+                            tile: tile,
+                            player_id: user_id,
+                            pk: pk,
+                            game_id: game_id,
+                        });
+                    },
+                    MessageType::MutePlayer {target_id, muted, user_id, pk, game_id} => {
+                        self.data.do_send(server::MutePlayerMessage {
+                            target_id: target_id,
+                            muted: muted,
+                            player_id: user_id,
+                            pk: pk,
+                            game_id: game_id,
+                        });
+                    },
                     MessageType::Prune { password } => {
                         if Ok(password) == env::var("LD47_PASSWORD") {
                             self.data.send(server::MessagePrune {
@@ -189,6 +310,42 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameWebSocket {
                                 addr: ctx.address().recipient(),
                             })
                             .into_actor(self)
+                            .then(|res, act, ctx| {
+                                match res {
+                                    Ok(res) => act.id = res,
+                                    // something is wrong with server
+                                    _ => ctx.stop(),
+                                }
+                                fut::ready(())
+                            })
+                            .wait(ctx);
+                        } else {
+                            let _ = ctx.address().recipient().do_send(
+                                server::ToUserMessage(String::from("Wrong password"))
+                            );
+                        }
+                    },
+                    MessageType::AdminMute { password, game_id, player_id, muted } => {
+                        if Ok(password) == env::var("LD47_PASSWORD") {
+                            self.data.do_send(server::MessageAdminMute {
+                                game_id: game_id,
+                                player_id: player_id,
+                                muted: muted,
+                                addr: ctx.address().recipient(),
+                            });
+                        } else {
+                            let _ = ctx.address().recipient().do_send(
+                                server::ToUserMessage(String::from("Wrong password"))
+                            );
+                        }
+                    },
+                    MessageType::Stats { password, game_id } => {
+                        if Ok(password) == env::var("LD47_PASSWORD") {
+                            self.data.send(server::MessageStats {
+                                game_id: game_id,
+                                addr: ctx.address().recipient(),
+                            })
+                            .into_actor(self)
                             .then(|res, act, ctx| {
                                 match res {
                                     Ok(res) => act.id = res as usize,
@@ -214,21 +371,77 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameWebSocket {
 }
 
 async fn index(
-    req: HttpRequest, stream: web::Payload, data: web::Data<Addr<server::GameServer>>,
+    req: HttpRequest,
+    stream: web::Payload,
+    data: web::Data<Addr<server::GameServer>>,
+    matchmaker: web::Data<Addr<matchmaking::Matchmaker>>,
 ) -> Result<HttpResponse, Error> {
     let resp = ws::start(GameWebSocket {
         id: 0,
         data: data.get_ref().clone(),
+        matchmaker: matchmaker.get_ref().clone(),
     }, &req, stream);
     println!("{:?}", resp);
     resp
 }
 
+/// Starts draining the game server and the matchmaker when the process gets SIGTERM
+async fn drain_on_sigterm(game_server: Addr<server::GameServer>, matchmaker: Addr<matchmaking::Matchmaker>) {
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            terminate.recv().await;
+            println!("Got SIGTERM");
+            matchmaker.do_send(server::Drain { secs: server::DEFAULT_DRAIN_SECS });
+            let _ = game_server.send(server::Drain { secs: server::DEFAULT_DRAIN_SECS }).await;
+        },
+        Err(err) => println!("Failed to listen for SIGTERM: {}", err),
+    }
+}
+
+async fn stop_on_ctrl_c(http_server: Server) {
+    if ctrl_c().await.is_ok() {
+        http_server.stop(true).await;
+    }
+}
+
+/// Stops the http server once the game server has drained, whether the drain came from SIGTERM or the admin API
+async fn stop_when_drained(game_server: Addr<server::GameServer>, http_server: Server) {
+    loop {
+        delay_for(Duration::from_secs(1)).await;
+        if let Ok(true) = game_server.send(server::DrainStatus).await {
+            http_server.stop(true).await;
+            return;
+        }
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let server = actix::Supervisor::start(|_| server::GameServer::new());
+    let maps_dir = env::var("LD47_MAPS_DIR").unwrap_or_else(|_| String::from("maps"));
+    let maps = map::MapDefinition::load_dir(Path::new(&maps_dir), server::MAX_PLAYERS);
+    println!("Loaded {} maps", maps.len());
 
-    if Ok(String::from("SSL")) == env::var("MODE") {
+    if env::args().nth(1).as_deref() == Some("suggest-weights") {
+        let games = env::args().nth(2).and_then(|games| games.parse().ok()).unwrap_or(1000);
+        balance::suggest_weights_command(games, &maps);
+        return Ok(());
+    }
+
+    let db_path = env::var("SQLITE_PATH").unwrap_or_else(|_| String::from("ld47.sqlite3"));
+    let storage: storage::SharedStorage = Arc::new(Mutex::new(
+        storage::SqliteStorage::open(&db_path).expect("failed to open database")
+    ));
+    let catalogue = cards::CardCatalogue::from_env();
+    println!("Loaded {} cards", catalogue.cards.len());
+
+    let server_storage = storage.clone();
+    let server = actix::Supervisor::start(move |_| server::GameServer::new(server_storage.clone(), maps.clone(), catalogue.clone()));
+    let username_filter = Arc::new(validation::UsernameFilter::from_env());
+    let matchmaker = matchmaking::Matchmaker::new(server.clone(), storage.clone(), username_filter.clone()).start();
+
+    let drain_server = server.clone();
+    let drain_matchmaker = matchmaker.clone();
+    let http_server = if Ok(String::from("SSL")) == env::var("MODE") {
         let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
         builder
             .set_private_key_file("key.pem", SslFiletype::PEM)
@@ -238,19 +451,42 @@ async fn main() -> std::io::Result<()> {
         HttpServer::new(move || {
             App::new()
             .data(server.clone())
+            .data(matchmaker.clone())
+            .data(storage.clone())
+            .data(username_filter.clone())
             .route("/", web::get().to(index))
+            .route("/players", web::post().to(api::claim_username))
+            .route("/players/{username}/matches", web::get().to(api::match_history))
+            .route("/leaderboard", web::get().to(api::leaderboard))
+            .route("/admin/cards", web::get().to(api::card_usage))
+            .route("/admin/drain", web::post().to(api::drain))
         })
+            .disable_signals()
             .bind_openssl("0.0.0.0:443", builder)?
             .run()
-            .await
     } else {
         HttpServer::new(move || {
             App::new()
             .data(server.clone())
+            .data(matchmaker.clone())
+            .data(storage.clone())
+            .data(username_filter.clone())
             .route("/", web::get().to(index))
+            .route("/players", web::post().to(api::claim_username))
+            .route("/players/{username}/matches", web::get().to(api::match_history))
+            .route("/leaderboard", web::get().to(api::leaderboard))
+            .route("/admin/cards", web::get().to(api::card_usage))
+            .route("/admin/drain", web::post().to(api::drain))
         })
+            .disable_signals()
             .bind("0.0.0.0:8080")?
             .run()
-            .await
-    }
+    };
+
+    // Signals are handled here instead of by actix so SIGTERM lets running games finish
+    actix_web::rt::spawn(drain_on_sigterm(drain_server.clone(), drain_matchmaker));
+    actix_web::rt::spawn(stop_on_ctrl_c(http_server.clone()));
+    actix_web::rt::spawn(stop_when_drained(drain_server, http_server.clone()));
+
+    http_server.await
 }
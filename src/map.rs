@@ -0,0 +1,256 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum HazardKind {
+    Damage { amount: u8 },
+    /// Moves whoever ends a turn on it one tile in `direction` (same encoding as orientations)
+    Conveyor { direction: u8 },
+    Teleporter { target: (u16, u16) },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hazard {
+    pub tile: (u16, u16),
+    #[serde(flatten)]
+    pub kind: HazardKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapDefinition {
+    pub name: String,
+    pub board_size: (u16, u16),
+    #[serde(default)]
+    pub walls: Vec<(u16, u16)>,
+    #[serde(default)]
+    pub hazards: Vec<Hazard>,
+    /// When empty players spawn on random free tiles
+    #[serde(default)]
+    pub spawn_points: Vec<(u16, u16)>,
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(String),
+    Parse(String),
+    EmptyBoard,
+    OutOfBounds { tile: (u16, u16) },
+    BlockedSpawn { tile: (u16, u16) },
+    NotEnoughSpawns { needed: usize, found: usize },
+    Unreachable { tile: (u16, u16) },
+    BlockedTeleport { tile: (u16, u16) },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(err) => write!(f, "Failed to read map: {}", err),
+            MapError::Parse(err) => write!(f, "Failed to parse map: {}", err),
+            MapError::EmptyBoard => write!(f, "Board has no tiles"),
+            MapError::OutOfBounds { tile } => write!(f, "Tile {:?} is outside the board", tile),
+            MapError::BlockedSpawn { tile } => write!(f, "Spawn point {:?} is on a wall or hazard", tile),
+            MapError::NotEnoughSpawns { needed, found } => write!(f, "Map needs {} spawn points, found {}", needed, found),
+            MapError::Unreachable { tile } => write!(f, "Spawn point {:?} can't be reached from the others", tile),
+            MapError::BlockedTeleport { tile } => write!(f, "Teleporter on {:?} leads into a wall", tile),
+        }
+    }
+}
+
+impl MapDefinition {
+    /// An open board without terrain
+    pub fn open(board_size: (u16, u16)) -> Self {
+        MapDefinition {
+            name: String::from("Open"),
+            board_size: board_size,
+            walls: Vec::new(),
+            hazards: Vec::new(),
+            spawn_points: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MapError> {
+        let contents = fs::read_to_string(path).map_err(|err| MapError::Io(err.to_string()))?;
+        serde_json::from_str(&contents).map_err(|err| MapError::Parse(err.to_string()))
+    }
+
+    /// Loads every `.json` map in a directory, maps that fail to load or validate are skipped
+    pub fn load_dir(dir: &Path, max_players: usize) -> Vec<Self> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                println!("Failed to read maps from {}: {}", dir.display(), err);
+                return Vec::new();
+            },
+        };
+
+        let mut maps = Vec::new();
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.extension().map(|ext| ext != "json").unwrap_or(true) {
+                continue;
+            }
+
+            match MapDefinition::load(&path).and_then(|map| map.validate(max_players).map(|_| map)) {
+                Ok(map) => maps.push(map),
+                Err(err) => println!("Skipping map {}: {}", path.display(), err),
+            }
+        }
+
+        maps
+    }
+
+    pub fn is_on_board(&self, tile: (u16, u16)) -> bool {
+        tile.0 < self.board_size.0 && tile.1 < self.board_size.1
+    }
+
+    pub fn is_wall(&self, tile: (u16, u16)) -> bool {
+        self.walls.contains(&tile)
+    }
+
+    pub fn hazard_at(&self, tile: (u16, u16)) -> Option<&Hazard> {
+        self.hazards.iter().find(|hazard| hazard.tile == tile)
+    }
+
+    /// Whether a player can be placed on the tile at the start of a game
+    pub fn is_free(&self, tile: (u16, u16)) -> bool {
+        self.is_on_board(tile) && !self.is_wall(tile) && self.hazard_at(tile).is_none()
+    }
+
+    pub fn free_tiles(&self) -> Vec<(u16, u16)> {
+        (0 .. self.board_size.0)
+            .flat_map(|x| (0 .. self.board_size.1).map(move |y| (x, y)))
+            .filter(|tile| self.is_free(*tile))
+            .collect()
+    }
+
+    /// Where players can spawn when the map has no spawn points, row 0 is kept clear
+    pub fn random_spawn_tiles(&self) -> Vec<(u16, u16)> {
+        self.free_tiles().into_iter().filter(|tile| tile.1 > 0).collect()
+    }
+
+    pub fn validate(&self, max_players: usize) -> Result<(), MapError> {
+        if self.board_size.0 == 0 || self.board_size.1 == 0 {
+            return Err(MapError::EmptyBoard);
+        }
+
+        let teleport_targets = self.hazards.iter().filter_map(|hazard| match hazard.kind {
+            HazardKind::Teleporter { target } => Some(target),
+            _ => None,
+        });
+        let tiles = self.walls.iter().cloned()
+            .chain(self.hazards.iter().map(|hazard| hazard.tile))
+            .chain(self.spawn_points.iter().cloned())
+            .chain(teleport_targets);
+        for tile in tiles {
+            if !self.is_on_board(tile) {
+                return Err(MapError::OutOfBounds { tile: tile });
+            }
+        }
+
+        for hazard in self.hazards.iter() {
+            if let HazardKind::Teleporter { target } = hazard.kind {
+                if self.is_wall(target) {
+                    return Err(MapError::BlockedTeleport { tile: hazard.tile });
+                }
+            }
+        }
+
+        if let Some(tile) = self.spawn_points.iter().find(|tile| !self.is_free(**tile)) {
+            return Err(MapError::BlockedSpawn { tile: *tile });
+        }
+
+        let unique_spawns: HashSet<_> = self.spawn_points.iter().collect();
+        let spawn_count = if self.spawn_points.is_empty() {
+            self.random_spawn_tiles().len()
+        } else {
+            unique_spawns.len()
+        };
+        if spawn_count < max_players {
+            return Err(MapError::NotEnoughSpawns { needed: max_players, found: spawn_count });
+        }
+
+        if let Some(first) = self.spawn_points.first() {
+            let reachable = self.reachable_from(*first);
+            if let Some(tile) = self.spawn_points.iter().find(|tile| !reachable.contains(tile)) {
+                return Err(MapError::Unreachable { tile: *tile });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flood fills the tiles that can be walked to from `start`, following teleporters
+    fn reachable_from(&self, start: (u16, u16)) -> HashSet<(u16, u16)> {
+        let mut reachable = HashSet::new();
+        let mut queue = VecDeque::new();
+        reachable.insert(start);
+        queue.push_back(start);
+
+        while let Some((x, y)) = queue.pop_front() {
+            let mut next = vec![(x + 1, y), (x, y + 1)];
+            if x > 0 { next.push((x - 1, y)); }
+            if y > 0 { next.push((x, y - 1)); }
+            if let Some(Hazard { kind: HazardKind::Teleporter { target }, .. }) = self.hazard_at((x, y)) {
+                next.push(*target);
+            }
+
+            for tile in next {
+                if self.is_on_board(tile) && !self.is_wall(tile) && reachable.insert(tile) {
+                    queue.push_back(tile);
+                }
+            }
+        }
+
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(board_size: (u16, u16), walls: Vec<(u16, u16)>, hazards: Vec<Hazard>) -> MapDefinition {
+        MapDefinition {
+            name: String::from("Test"),
+            board_size: board_size,
+            walls: walls,
+            hazards: hazards,
+            spawn_points: Vec::new(),
+        }
+    }
+
+    fn teleporter(tile: (u16, u16), target: (u16, u16)) -> Hazard {
+        Hazard { tile: tile, kind: HazardKind::Teleporter { target: target } }
+    }
+
+    #[test]
+    fn random_spawns_skip_row_zero() {
+        // 3 columns by 2 rows leaves 3 tiles outside row 0
+        assert!(map((3, 2), Vec::new(), Vec::new()).validate(3).is_ok());
+        match map((3, 2), Vec::new(), Vec::new()).validate(4) {
+            Err(MapError::NotEnoughSpawns { needed: 4, found: 3 }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn teleporters_must_lead_onto_the_board() {
+        match map((4, 4), Vec::new(), vec![teleporter((1, 1), (4, 0))]).validate(2) {
+            Err(MapError::OutOfBounds { tile: (4, 0) }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn teleporters_cant_lead_into_walls() {
+        match map((4, 4), vec![(3, 3)], vec![teleporter((1, 1), (3, 3))]).validate(2) {
+            Err(MapError::BlockedTeleport { tile: (1, 1) }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(map((4, 4), vec![(3, 3)], vec![teleporter((1, 1), (2, 3))]).validate(2).is_ok());
+    }
+}
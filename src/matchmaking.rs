@@ -0,0 +1,236 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use actix::prelude::*;
+use serde::Serialize;
+
+use crate::config::{GameConfig, GameMode, QueueType};
+use crate::rating::DEFAULT_RATING;
+use crate::server::{Connect, CreateLobby, Drain, GameServer, ServerShuttingDown, ToUserMessage};
+use crate::storage::SharedStorage;
+use crate::validation::{InvalidUsername, UsernameFilter};
+
+const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(1);
+/// A lobby is started as soon as this many compatible humans are waiting
+const LOBBY_HUMANS: usize = 4;
+/// After waiting this long a player is put in a lobby with whoever is compatible (as long as there
+/// are the lobby's minimum humans), bots fill the rest
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(20);
+const RATING_BAND: f64 = 150.0;
+const RATING_BAND_GROWTH_PER_SEC: f64 = 25.0;
+const LATENCY_BAND_MS: u32 = 80;
+const LATENCY_BAND_GROWTH_PER_SEC: u32 = 10;
+
+#[derive(Message)]
+#[rtype(usize)]
+pub struct JoinQueue {
+    pub connect: Connect,
+    pub mode: GameMode,
+    pub queue: QueueType,
+    pub latency_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct Queued {
+    pub players_waiting: usize,
+}
+
+/// Sent instead of queueing when the requested character doesn't exist
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct InvalidCharacter {
+    pub character_type: u8,
+    pub characters: Vec<String>,
+}
+
+struct QueuedPlayer {
+    connect: Connect,
+    mode: GameMode,
+    queue: QueueType,
+    rating: f64,
+    latency_ms: Option<u32>,
+    queued_at: Instant,
+}
+
+impl QueuedPlayer {
+    /// Whether `other` can share a lobby with this player, the bands widen the longer this player waits
+    fn is_compatible(&self, other: &QueuedPlayer, waited: Duration) -> bool {
+        let rating_band = RATING_BAND + RATING_BAND_GROWTH_PER_SEC * waited.as_secs_f64();
+        let latency_band = LATENCY_BAND_MS + LATENCY_BAND_GROWTH_PER_SEC * waited.as_secs() as u32;
+
+        let latency_ok = match (self.latency_ms, other.latency_ms) {
+            (Some(a), Some(b)) => (a as i64 - b as i64).abs() <= latency_band as i64,
+            _ => true,
+        };
+
+        self.mode == other.mode && self.queue == other.queue && (self.rating - other.rating).abs() <= rating_band && latency_ok
+    }
+}
+
+/// Batches waiting players into lobbies of similar rating and latency
+pub struct Matchmaker {
+    server: Addr<GameServer>,
+    storage: SharedStorage,
+    username_filter: Arc<UsernameFilter>,
+    gc: GameConfig,
+    queue: Vec<QueuedPlayer>,
+    /// Set once the server is draining, nobody is queued after that
+    draining: bool,
+}
+
+impl Matchmaker {
+    pub fn new(server: Addr<GameServer>, storage: SharedStorage, username_filter: Arc<UsernameFilter>) -> Matchmaker {
+        Matchmaker {
+            server: server,
+            storage: storage,
+            username_filter: username_filter,
+            gc: GameConfig::default(),
+            queue: Vec::new(),
+            draining: false,
+        }
+    }
+
+    /// Tells a player no games are starting anymore
+    fn reject_draining(addr: &Option<Recipient<ToUserMessage>>) {
+        if let (Some(ref addr), Ok(json_string)) = (addr, serde_json::to_string(&ServerShuttingDown { secs: 0 })) {
+            let _ = addr.do_send(ToUserMessage(json_string));
+        }
+    }
+
+    fn form_lobbies(&mut self) {
+        // Drop players that left while waiting
+        self.queue.retain(|p| p.connect.addr.as_ref().map(|addr| addr.connected()).unwrap_or(false));
+        self.queue.sort_by_key(|p| p.queued_at);
+
+        let now = Instant::now();
+        let mut i = 0;
+        // The longest waiting player anchors each lobby
+        while i < self.queue.len() {
+            let waited = now.duration_since(self.queue[i].queued_at);
+            let lobby = self.gc.lobby_settings(self.queue[i].queue);
+            let members: Vec<usize> = (i .. self.queue.len())
+                .filter(|j| self.queue[i].is_compatible(&self.queue[*j], waited))
+                .take(lobby.max_players)
+                .collect();
+
+            let enough_humans = members.len() >= LOBBY_HUMANS.max(lobby.min_humans).min(lobby.max_players);
+            let timed_out = waited >= QUEUE_TIMEOUT && members.len() >= lobby.min_humans;
+            if enough_humans || timed_out {
+                let mode = self.queue[i].mode;
+                let queue = self.queue[i].queue;
+                let mut players = Vec::new();
+                for j in members.iter().rev() {
+                    players.push(self.queue.remove(*j).connect);
+                }
+                players.reverse();
+
+                self.server.do_send(CreateLobby {
+                    mode: mode,
+                    queue: queue,
+                    players: players,
+                });
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+impl Actor for Matchmaker {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(MATCHMAKING_INTERVAL, |act, _| act.form_lobbies());
+    }
+}
+
+impl Handler<JoinQueue> for Matchmaker {
+    type Result = usize;
+
+    fn handle(&mut self, mut msg: JoinQueue, _: &mut Context<Self>) -> Self::Result {
+        if self.draining {
+            Self::reject_draining(&msg.connect.addr);
+            return 0;
+        }
+
+        match self.username_filter.validate(&msg.connect.username) {
+            Ok(username) => msg.connect.username = username,
+            Err(err) => {
+                if let Ok(json_string) = serde_json::to_string(&InvalidUsername::from(err)) {
+                    if let Some(ref addr) = msg.connect.addr {
+                        let _ = addr.do_send(ToUserMessage(json_string));
+                    }
+                }
+                return 0;
+            },
+        }
+
+        if msg.connect.character_type as usize >= self.gc.characters.len() {
+            let invalid = InvalidCharacter {
+                character_type: msg.connect.character_type,
+                characters: self.gc.characters.iter().map(|c| c.name.clone()).collect(),
+            };
+            if let Ok(json_string) = serde_json::to_string(&invalid) {
+                if let Some(ref addr) = msg.connect.addr {
+                    let _ = addr.do_send(ToUserMessage(json_string));
+                }
+            }
+            return 0;
+        }
+
+        let account = match msg.connect.account_token {
+            Some(ref token) => match self.storage.lock().unwrap().find_account(token) {
+                Ok(account) => account,
+                Err(err) => {
+                    println!("{}", err);
+                    None
+                },
+            },
+            None => None,
+        };
+        let rating = account.as_ref().map(|a| a.rating).unwrap_or(DEFAULT_RATING);
+
+        // Ratings are kept per account, so ranked games are only open to players with one
+        if msg.queue == QueueType::Ranked && account.is_none() {
+            if let Some(ref addr) = msg.connect.addr {
+                let _ = addr.do_send(ToUserMessage(String::from("Ranked games need an account")));
+            }
+            return 0;
+        }
+
+        println!("{} queued", msg.connect.username);
+        self.queue.push(QueuedPlayer {
+            connect: msg.connect,
+            mode: msg.mode,
+            queue: msg.queue,
+            rating: rating,
+            latency_ms: msg.latency_ms,
+            queued_at: Instant::now(),
+        });
+
+        let status = Queued { players_waiting: self.queue.len() };
+        if let Ok(json_string) = serde_json::to_string(&status) {
+            if let Some(ref addr) = self.queue.last().unwrap().connect.addr {
+                let _ = addr.do_send(ToUserMessage(json_string));
+            }
+        }
+
+        self.queue.len()
+    }
+}
+
+/// Empties the queue and refuses everyone who joins it from now on
+impl Handler<Drain> for Matchmaker {
+    type Result = usize;
+
+    fn handle(&mut self, _: Drain, _: &mut Context<Self>) -> Self::Result {
+        self.draining = true;
+        for player in self.queue.iter() {
+            Self::reject_draining(&player.connect.addr);
+        }
+
+        let dropped = self.queue.len();
+        self.queue.clear();
+        dropped
+    }
+}
@@ -1,11 +1,20 @@
-use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
 use actix::prelude::*;
 use rand::prelude::*;
 use rand::distributions::Alphanumeric;
 
+use crate::config::{GameConfig, GameMode, LobbySettings, QueueType};
+use crate::map::MapDefinition;
 use crate::server::ToUserMessage;
+use crate::replay::ReplayEvent;
+use crate::simulator::{SimEvent, Simulator};
 
-#[derive(Debug, Clone, Serialize)]
+/// Upper bound on team ids, players without a team are counted as their own side above it
+const MAX_TEAMS: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub struct Mutation {
     pub user_id: usize,
@@ -20,66 +29,346 @@ pub struct Player {
     pub username: String,
     pub private_key: String,
     pub character_type: u8,
+    pub team: Option<u8>,
     pub pos: (u16, u16, u8),
     pub is_ai: bool,
     pub active: bool,
+    /// Kicked from the lobby, the slot is kept so the players after them keep their ids
+    #[serde(skip)]
+    pub kicked: bool,
+    pub health: u8,
+    pub cosmetics: Cosmetics,
+    #[serde(skip)]
+    pub account_id: Option<i64>,
+    #[serde(skip)]
+    pub rating: f64,
     #[serde(skip)]
     pub previous_choices: Vec<Mutation>,
     #[serde(skip)]
     pub card_options: Option<Vec<u8>>,
+    /// The cards programmed this turn by slot
+    #[serde(skip)]
+    pub program: Vec<Option<Mutation>>,
     #[serde(skip)]
     pub animation_done: bool,
     #[serde(skip)]
     pub addr: Option<Recipient<ToUserMessage>>,
     pub game_id: usize,
     #[serde(skip)]
-    pub has_death_voted: bool,
+    pub stats: PlayerStats,
+    #[serde(skip)]
+    pub recent_chats: VecDeque<Instant>,
+    #[serde(skip)]
+    pub muted_players: HashSet<usize>,
+    #[serde(skip)]
+    pub chat_muted: bool,
+    #[serde(skip)]
+    pub last_emote: Option<Instant>,
+    #[serde(skip)]
+    pub ready: bool,
+    #[serde(skip)]
+    pub deck: Option<Deck>,
     #[serde(skip)]
-    pub been_death_voted_for: usize,
+    pub status_effects: Vec<StatusEffect>,
+}
+
+/// Per player statistics accumulated over the course of a game
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub turn_of_death: Option<usize>,
+    pub killer_id: Option<usize>,
+    pub cards_offered: HashMap<u8, usize>,
+    pub cards_played: HashMap<u8, usize>,
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    pub distance_moved: u32,
+}
+
+/// Who joins a game, everything else about a new player starts out the same
+pub struct NewPlayer {
+    pub id: usize,
+    pub game_id: usize,
+    pub username: String,
+    pub character_type: u8,
+    pub team: Option<u8>,
+    pub pos: (u16, u16),
+    pub is_ai: bool,
+    pub health: u8,
+    pub cosmetics: Cosmetics,
+    pub account_id: Option<i64>,
+    pub rating: f64,
+    pub addr: Option<Recipient<ToUserMessage>>,
+}
+
+#[cfg(test)]
+impl NewPlayer {
+    /// A human without an account or team, tests change the fields they care about
+    pub fn test(id: usize, pos: (u16, u16)) -> NewPlayer {
+        NewPlayer {
+            id: id,
+            game_id: 0,
+            username: format!("Player {}", id),
+            character_type: 0,
+            team: None,
+            pos: pos,
+            is_ai: false,
+            health: 10,
+            cosmetics: Cosmetics::default(),
+            account_id: None,
+            rating: crate::rating::DEFAULT_RATING,
+            addr: None,
+        }
+    }
 }
 
 impl Player {
-    pub fn new(
-        id: usize,
-        game_id: usize,
-        username: String,
-        character_type: u8,
-        pos: (u16, u16),
-        is_ai: bool,
-        addr: Option<Recipient<ToUserMessage>>,
-        rng: &mut ThreadRng
-    ) -> Player {
+    pub fn new<R: Rng>(new: NewPlayer, rng: &mut R) -> Player {
         return Player {
-            id: id,
-            username: username,
+            id: new.id,
+            username: new.username,
             private_key: rng.sample_iter(&Alphanumeric).take(10).collect::<String>(),
-            character_type: character_type,
-            pos: (pos.0, pos.1, rng.gen_range(0, 4)),
-            is_ai: is_ai,
+            character_type: new.character_type,
+            team: new.team,
+            pos: (new.pos.0, new.pos.1, rng.gen_range(0, 4)),
+            is_ai: new.is_ai,
             active: true,
+            kicked: false,
+            health: new.health,
+            cosmetics: new.cosmetics,
+            account_id: new.account_id,
+            rating: new.rating,
             previous_choices: Vec::new(),
             card_options: None,
+            program: Vec::new(),
             animation_done: false,
-            addr: addr,
-            game_id: game_id,
-            has_death_voted: false,
-            been_death_voted_for: 0,
+            addr: new.addr,
+            game_id: new.game_id,
+            stats: PlayerStats::default(),
+            recent_chats: VecDeque::new(),
+            muted_players: HashSet::new(),
+            chat_muted: false,
+            last_emote: None,
+            ready: false,
+            deck: None,
+            status_effects: Vec::new(),
+        }
+    }
+
+    pub fn has_status(&self, kind: StatusKind) -> bool {
+        self.status_effects.iter().any(|effect| effect.kind == kind)
+    }
+
+    /// Applies an effect from now until `turns` turns after `turn_index`, extending one already active
+    pub fn add_status(&mut self, kind: StatusKind, turn_index: usize, turns: u8) {
+        let expires_after_turn = turn_index + turns as usize;
+        match self.status_effects.iter_mut().find(|effect| effect.kind == kind) {
+            Some(effect) => effect.expires_after_turn = effect.expires_after_turn.max(expires_after_turn),
+            None => self.status_effects.push(StatusEffect { kind: kind, expires_after_turn: expires_after_turn }),
+        }
+    }
+
+    pub fn program_complete(&self) -> bool {
+        self.program.iter().all(|slot| slot.is_some())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StatusKind {
+    /// Moves go in a random direction
+    Disoriented,
+    /// Cards are skipped
+    Stunned,
+    /// Damage is blocked
+    Shielded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    /// The last turn the effect applies to
+    pub expires_after_turn: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Deck {
+    pub draw_pile: Vec<u8>,
+    /// Oldest card first
+    pub hand: Vec<u8>,
+    pub discard_pile: Vec<u8>,
+    /// Cards of the hand offered this turn
+    #[serde(default)]
+    pub options: Vec<u8>,
+}
+
+impl Deck {
+    pub fn new<R: Rng>(mut cards: Vec<u8>, rng: &mut R) -> Self {
+        cards.shuffle(rng);
+
+        Deck {
+            draw_pile: cards,
+            hand: Vec::new(),
+            discard_pile: Vec::new(),
+            options: Vec::new(),
         }
     }
+
+    /// Draws the top card, shuffling the discard pile back in once the draw pile runs out
+    pub fn draw<R: Rng>(&mut self, rng: &mut R) -> Option<u8> {
+        if self.draw_pile.is_empty() {
+            self.draw_pile.append(&mut self.discard_pile);
+            self.draw_pile.shuffle(rng);
+        }
+
+        self.draw_pile.pop()
+    }
+
+    pub fn fill_hand<R: Rng>(&mut self, hand_size: usize, rng: &mut R) {
+        while self.hand.len() < hand_size {
+            match self.draw(rng) {
+                Some(card) => self.hand.push(card),
+                None => break,
+            }
+        }
+    }
+
+    /// Discards the options of the last turn that weren't played, refills the hand and
+    /// offers its oldest `options` cards
+    pub fn deal_options<R: Rng>(&mut self, options: usize, hand_size: usize, rng: &mut R) -> Vec<u8> {
+        for card in std::mem::take(&mut self.options) {
+            self.discard(card);
+        }
+        self.fill_hand(hand_size.max(options), rng);
+        self.options = self.hand.iter().take(options).cloned().collect();
+
+        self.options.clone()
+    }
+
+    /// Moves a played card from the hand to the discard pile
+    pub fn discard(&mut self, card: u8) {
+        if let Some(index) = self.hand.iter().position(|c| *c == card) {
+            self.discard_pile.push(self.hand.remove(index));
+        }
+        if let Some(index) = self.options.iter().position(|c| *c == card) {
+            self.options.remove(index);
+        }
+    }
+}
+
+/// Inclusive tile bounds of the playable area
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub min_x: u16,
+    pub min_y: u16,
+    pub max_x: u16,
+    pub max_y: u16,
+}
+
+impl Bounds {
+    pub fn from_board_size(board_size: (u16, u16)) -> Self {
+        Bounds {
+            min_x: 0,
+            min_y: 0,
+            max_x: board_size.0 - 1,
+            max_y: board_size.1 - 1,
+        }
+    }
+
+    /// Contracts the bounds by one ring of tiles, each axis stops at `min_size`
+    pub fn shrink(&self, min_size: (u16, u16)) -> Self {
+        let mut shrunk = *self;
+        if self.max_x - self.min_x + 1 >= min_size.0 + 2 {
+            shrunk.min_x += 1;
+            shrunk.max_x -= 1;
+        }
+        if self.max_y - self.min_y + 1 >= min_size.1 + 2 {
+            shrunk.min_y += 1;
+            shrunk.max_y -= 1;
+        }
+
+        shrunk
+    }
+
+    pub fn contains(&self, tile: (u16, u16)) -> bool {
+        (self.min_x ..= self.max_x).contains(&tile.0) && (self.min_y ..= self.max_y).contains(&tile.1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Cosmetics {
+    /// Index in `CosmeticSettings::palette`
+    pub color: u8,
+    pub hat: Option<u8>,
+    pub skin: Option<u8>,
 }
 
 pub struct Game {
+    pub mode: GameMode,
+    pub queue: QueueType,
+    pub settings: LobbySettings,
+    /// The human who can change the lobby settings before the game starts
+    pub host_id: Option<usize>,
+    pub countdown_deadline: Instant,
+    /// The countdown deadline from before every human was ready
+    pub unready_deadline: Option<Instant>,
+    pub map: MapDefinition,
+    pub map_candidates: Vec<MapDefinition>,
+    /// Player id to the index of the candidate map they voted for
+    pub map_votes: HashMap<usize, usize>,
     pub board_size: (u16, u16),
+    pub bounds: Bounds,
     pub game_started: bool,
     pub players: Vec<Player>,
     pub turn_index: usize,
+    /// Whether the cards of the current turn have been played out
+    pub turn_resolved: bool,
     pub available_pos: Vec<(u16, u16)>,
     pub game_countdown_handle: Option<SpawnHandle>,
     pub has_loop_countdown: bool,
     pub game_tick_handle: SpawnHandle,
+    pub elimination_order: Vec<usize>,
+    pub replay_log: Vec<ReplayEvent>,
+    /// Humans have until then to rejoin a game restored after the server restarted
+    pub rejoin_deadline: Option<Instant>,
 }
 
 impl Game {
+    /// An empty lobby on the first of the candidate maps
+    pub fn new(
+        mode: GameMode,
+        queue: QueueType,
+        settings: LobbySettings,
+        map_candidates: Vec<MapDefinition>,
+        available_pos: Vec<(u16, u16)>,
+        game_tick_handle: SpawnHandle
+    ) -> Game {
+        let map = map_candidates[0].clone();
+
+        Game {
+            mode: mode,
+            queue: queue,
+            settings: settings,
+            host_id: None,
+            countdown_deadline: Instant::now(),
+            unready_deadline: None,
+            board_size: map.board_size,
+            bounds: Bounds::from_board_size(map.board_size),
+            map: map,
+            map_candidates: map_candidates,
+            map_votes: HashMap::new(),
+            game_started: false,
+            players: Vec::new(),
+            turn_index: 0,
+            turn_resolved: false,
+            available_pos: available_pos,
+            game_countdown_handle: None,
+            has_loop_countdown: false,
+            game_tick_handle: game_tick_handle,
+            elimination_order: Vec::new(),
+            replay_log: Vec::new(),
+            rejoin_deadline: None,
+        }
+    }
+
     pub fn get_cloned_players_id_addr(&self) -> Vec<(usize, Recipient<ToUserMessage>)> {
         let mut ret = Vec::new();
 
@@ -91,6 +380,137 @@ impl Game {
 
         ret
     }
+
+    /// Humans in the game that are still connected
+    pub fn connected_humans(&self) -> Vec<&Player> {
+        self.players.iter()
+            .filter(|p| !p.is_ai)
+            .filter(|p| p.addr.as_ref().map(|addr| addr.connected()).unwrap_or(false))
+            .collect()
+    }
+
+    /// Hands the lobby to the longest waiting connected human if the host left, returns whether it changed
+    pub fn ensure_host(&mut self) -> bool {
+        let host_connected = self.host_id
+            .and_then(|host_id| self.players.get(host_id))
+            .map(|host| host.addr.as_ref().map(|addr| addr.connected()).unwrap_or(false))
+            .unwrap_or(false);
+        if host_connected {
+            return false;
+        }
+
+        let new_host = self.connected_humans().first().map(|p| p.id);
+        let changed = new_host != self.host_id;
+        self.host_id = new_host;
+
+        changed
+    }
+
+    /// Everyone in the game but the players kicked from the lobby
+    pub fn seated_players(&self) -> impl Iterator<Item = &Player> {
+        self.players.iter().filter(|p| !p.kicked)
+    }
+
+    /// Takes a player out of a lobby that hasn't started and frees their spawn. Their key is replaced
+    /// so anything they still send is refused. Returns their connection
+    pub fn kick_player<R: Rng>(&mut self, player_index: usize, rng: &mut R) -> Option<Recipient<ToUserMessage>> {
+        let player = &mut self.players[player_index];
+        player.kicked = true;
+        player.active = false;
+        player.ready = false;
+        player.private_key = rng.sample_iter(&Alphanumeric).take(10).collect::<String>();
+        self.available_pos.push((player.pos.0, player.pos.1));
+        self.map_votes.remove(&player_index);
+        if self.host_id == Some(player_index) {
+            self.host_id = None;
+        }
+
+        player.addr.take()
+    }
+
+    /// Number of votes for each candidate map
+    pub fn map_vote_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.map_candidates.len()];
+        for candidate in self.map_votes.values() {
+            counts[*candidate] += 1;
+        }
+
+        counts
+    }
+
+    /// Moves on to the next turn: expires status effects and in `GameMode::Loop` hurts the
+    /// players outside the ring before contracting it when it is due. Returns what the ring did
+    pub fn advance_turn(&mut self, gc: &GameConfig) -> Vec<SimEvent> {
+        for player in self.players.iter_mut() {
+            player.animation_done = false;
+        }
+
+        self.turn_index += 1;
+        self.turn_resolved = false;
+        let turn_index = self.turn_index;
+        for player in self.players.iter_mut() {
+            player.status_effects.retain(|effect| effect.expires_after_turn >= turn_index);
+        }
+
+        if self.mode != GameMode::Loop {
+            return Vec::new();
+        }
+
+        let events = Simulator::apply_ring_damage(self, gc);
+        if self.ring_shrinks_now(gc) {
+            self.bounds = self.bounds.shrink(gc.min_ring_size);
+        }
+
+        events
+    }
+
+    /// Whether the ring contracts this turn, a `shrink_every_turns` of 0 never contracts it
+    pub fn ring_shrinks_now(&self, gc: &GameConfig) -> bool {
+        self.turn_index > 0 && gc.shrink_every_turns > 0 && self.turn_index.is_multiple_of(gc.shrink_every_turns)
+    }
+
+    /// Number of players (or teams in team mode) still alive
+    pub fn remaining_sides(&self) -> usize {
+        let mut sides = HashSet::new();
+        for player in self.players.iter().filter(|p| p.active) {
+            sides.insert(player.team.map(|team| team as usize).unwrap_or(MAX_TEAMS + player.id));
+        }
+
+        sides.len()
+    }
+
+    pub fn is_on_board(&self, tile: (u16, u16)) -> bool {
+        tile.0 < self.board_size.0 && tile.1 < self.board_size.1
+    }
+
+    /// Average rating of the human players in the game
+    pub fn average_rating(&self) -> Option<f64> {
+        let ratings: Vec<_> = self.players.iter().filter(|p| !p.is_ai).map(|p| p.rating).collect();
+
+        if ratings.is_empty() {
+            None
+        } else {
+            Some(ratings.iter().sum::<f64>() / ratings.len() as f64)
+        }
+    }
+
+    /// Marks a player as dead and records when (and by whom) they were killed
+    pub fn eliminate_player(&mut self, player_index: usize, killer_id: Option<usize>) {
+        let turn_index = self.turn_index;
+        let player = &mut self.players[player_index];
+
+        if player.active {
+            player.active = false;
+            player.stats.turn_of_death = Some(turn_index);
+            player.stats.killer_id = killer_id;
+            self.elimination_order.push(player_index);
+            self.replay_log.push(ReplayEvent::PlayerDied {
+                turn: turn_index,
+                user_id: player_index,
+                killer_id: killer_id,
+            });
+        }
+    }
 }
 
 // Helper structs
@@ -105,6 +525,253 @@ pub struct Turn {
 #[serde(tag = "type")]
 pub struct CardOptions {
     pub card_options: Vec<u8>,
+    /// Number of slots to program, each option can fill one of them
+    pub slots: usize,
+    /// Every card in the player's hand when playing with decks, the options are the oldest of them
+    pub hand: Option<Vec<u8>>,
     pub player_id: usize,
     pub turn_id: usize,
 }
+
+/// How a card type fared across games, `winning_picks` counts picks by players who won, draws don't count
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CardUsage {
+    pub card: u8,
+    pub offered: usize,
+    pub picked: usize,
+    pub winning_picks: usize,
+}
+
+impl CardUsage {
+    /// Sums up the cards offered to and picked by the players of a finished game
+    pub fn from_game(game_over: &GameOver, include_bots: bool) -> Vec<CardUsage> {
+        let mut usage: HashMap<u8, CardUsage> = HashMap::new();
+        for result in game_over.players.iter().filter(|result| include_bots || !result.is_ai) {
+            for (card, count) in result.stats.cards_offered.iter() {
+                usage.entry(*card).or_insert(CardUsage { card: *card, ..CardUsage::default() }).offered += count;
+            }
+            for (card, count) in result.stats.cards_played.iter() {
+                let card_usage = usage.entry(*card).or_insert(CardUsage { card: *card, ..CardUsage::default() });
+                card_usage.picked += count;
+                if game_over.is_winner(result) {
+                    card_usage.winning_picks += count;
+                }
+            }
+        }
+
+        let mut usage: Vec<_> = usage.into_values().collect();
+        usage.sort_by_key(|card_usage| card_usage.card);
+
+        usage
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerResult {
+    pub user_id: usize,
+    pub username: String,
+    pub character_type: u8,
+    pub team: Option<u8>,
+    pub is_ai: bool,
+    pub placement: usize,
+    pub stats: PlayerStats,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct GameOver {
+    pub game_id: usize,
+    pub queue: QueueType,
+    pub winner_id: Option<usize>,
+    pub winning_team: Option<u8>,
+    pub turns: usize,
+    pub players: Vec<PlayerResult>,
+}
+
+impl GameOver {
+    /// Ranks the players by elimination order, survivors (or in team mode the whole winning team) share
+    /// first place and players eliminated on the same turn share a place. When the last sides die
+    /// together nobody survives and the game is a draw between them.
+    pub fn from(game_id: usize, game: &Game) -> Self {
+        let survivors: Vec<_> = game.players.iter().filter(|p| p.active).collect();
+        let winning_team = if game.mode == GameMode::Teams && game.remaining_sides() == 1 {
+            survivors.first().and_then(|p| p.team)
+        } else {
+            None
+        };
+        // Higher is better, the winning team ranks above everyone regardless of who in it survived
+        let standing = |p: &Player| {
+            if winning_team.is_some() && p.team == winning_team {
+                (true, true, 0)
+            } else {
+                (false, p.active, p.stats.turn_of_death.map(|turn| turn + 1).unwrap_or(0))
+            }
+        };
+
+        let ranked: Vec<_> = survivors.iter().cloned()
+            .chain(game.elimination_order.iter().rev().map(|player_index| &game.players[*player_index]))
+            .collect();
+        let mut players: Vec<_> = ranked.iter().map(|p| PlayerResult {
+            user_id: p.id,
+            username: p.username.clone(),
+            character_type: p.character_type,
+            team: p.team,
+            is_ai: p.is_ai,
+            placement: 1 + ranked.iter().filter(|other| standing(other) > standing(p)).count(),
+            stats: p.stats.clone(),
+        }).collect();
+        players.sort_by_key(|result| result.placement);
+
+        GameOver {
+            game_id: game_id,
+            queue: game.queue,
+            winner_id: if survivors.len() == 1 { Some(survivors[0].id) } else { None },
+            winning_team: winning_team,
+            turns: game.turn_index,
+            players: players,
+        }
+    }
+
+    /// Whether the player won the game, alone or with their team. Nobody wins a draw
+    pub fn is_winner(&self, result: &PlayerResult) -> bool {
+        self.winner_id == Some(result.user_id) || (self.winning_team.is_some() && result.team == self.winning_team)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    /// A started game with a player per team given, `None` for free for all
+    fn game(mode: GameMode, teams: &[Option<u8>]) -> Game {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = Game::new(
+            mode,
+            QueueType::Casual,
+            crate::config::GameConfig::default().lobby,
+            vec![MapDefinition::open((16, 9))],
+            Vec::new(),
+            SpawnHandle::default()
+        );
+        for (id, team) in teams.iter().enumerate() {
+            game.players.push(Player::new(NewPlayer { team: *team, ..NewPlayer::test(id, (id as u16, 1)) }, &mut rng));
+        }
+        game.game_started = true;
+        game
+    }
+
+    fn placements(game_over: &GameOver) -> Vec<(usize, usize)> {
+        let mut placements: Vec<_> = game_over.players.iter().map(|p| (p.user_id, p.placement)).collect();
+        placements.sort();
+        placements
+    }
+
+    #[test]
+    fn free_for_all_ranks_by_elimination() {
+        let mut game = game(GameMode::FreeForAll, &[None, None, None]);
+        game.eliminate_player(2, None);
+        game.turn_index += 1;
+        game.eliminate_player(0, Some(1));
+
+        let game_over = GameOver::from(0, &game);
+        assert_eq!(game_over.winner_id, Some(1));
+        assert_eq!(placements(&game_over), vec![(0, 2), (1, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn the_whole_winning_team_places_first() {
+        let mut game = game(GameMode::Teams, &[Some(0), Some(1), Some(0), Some(1)]);
+        game.eliminate_player(0, None);
+        game.turn_index += 1;
+        game.eliminate_player(1, None);
+        game.turn_index += 1;
+        game.eliminate_player(3, None);
+
+        let game_over = GameOver::from(0, &game);
+        assert_eq!(game_over.winning_team, Some(0));
+        assert_eq!(placements(&game_over), vec![(0, 1), (1, 4), (2, 1), (3, 3)]);
+    }
+
+    #[test]
+    fn sides_dying_together_draw() {
+        let mut game = game(GameMode::FreeForAll, &[None, None, None]);
+        game.eliminate_player(0, None);
+        game.turn_index += 1;
+        game.eliminate_player(1, Some(2));
+        game.eliminate_player(2, Some(1));
+
+        let game_over = GameOver::from(0, &game);
+        assert_eq!(game.remaining_sides(), 0);
+        assert_eq!(game_over.winner_id, None);
+        assert_eq!(placements(&game_over), vec![(0, 3), (1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn draws_have_no_winning_picks() {
+        let played = |game: &mut Game| {
+            for player in game.players.iter_mut() {
+                player.stats.cards_played.insert(4, 1);
+            }
+        };
+
+        let mut draw = game(GameMode::FreeForAll, &[None, None]);
+        played(&mut draw);
+        draw.eliminate_player(0, None);
+        draw.eliminate_player(1, None);
+        let usage = CardUsage::from_game(&GameOver::from(0, &draw), true);
+        assert_eq!((usage[0].picked, usage[0].winning_picks), (2, 0));
+
+        let mut won = game(GameMode::FreeForAll, &[None, None]);
+        played(&mut won);
+        won.eliminate_player(0, None);
+        let usage = CardUsage::from_game(&GameOver::from(0, &won), true);
+        assert_eq!((usage[0].picked, usage[0].winning_picks), (2, 1));
+    }
+
+    #[test]
+    fn no_winning_team_while_several_teams_are_alive() {
+        let game = game(GameMode::Teams, &[Some(0), Some(1)]);
+        assert_eq!(GameOver::from(0, &game).winning_team, None);
+    }
+
+    #[test]
+    fn kicked_players_keep_their_slot() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = game(GameMode::FreeForAll, &[None, None, None]);
+        game.game_started = false;
+        game.host_id = Some(1);
+        game.map_votes.insert(1, 0);
+        let private_key = game.players[1].private_key.clone();
+
+        game.kick_player(1, &mut rng);
+        let ids: Vec<_> = game.players.iter().map(|p| p.id).collect();
+        let seated: Vec<_> = game.seated_players().map(|p| p.id).collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(seated, vec![0, 2]);
+        assert_eq!(game.available_pos, vec![(1, 1)]);
+        assert_ne!(game.players[1].private_key, private_key);
+        assert_eq!(game.host_id, None);
+        assert!(game.map_votes.is_empty());
+    }
+
+    #[test]
+    fn unplayed_options_are_discarded_for_new_ones() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut deck = Deck::new(vec![0, 1, 2, 3, 4, 5], &mut rng);
+
+        let first = deck.deal_options(2, 4, &mut rng);
+        deck.discard(first[0]);
+        let second = deck.deal_options(2, 4, &mut rng);
+
+        assert_eq!(deck.hand.len(), 4);
+        assert_eq!(deck.discard_pile, first);
+        assert!(second.iter().all(|card| !first.contains(card)));
+    }
+
+    #[test]
+    fn an_empty_deck_deals_nothing() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(Deck::new(Vec::new(), &mut rng).deal_options(3, 5, &mut rng).is_empty());
+    }
+}
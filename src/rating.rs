@@ -0,0 +1,92 @@
+pub const DEFAULT_RATING: f64 = 1500.0;
+const K_FACTOR: f64 = 32.0;
+
+/// Expected score of a player against an opponent under the Elo model
+fn expected_score(rating: f64, other_rating: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((other_rating - rating) / 400.0))
+}
+
+/// Elo adapted to free-for-all games. Every pair of players is scored as a
+/// head to head match decided by placement and the K factor is spread over
+/// the number of opponents, so a game moves a rating about as much as one duel.
+///
+/// Takes `(rating, placement)` for each player and returns the new ratings in
+/// the same order.
+pub fn update_ratings(players: &[(f64, usize)]) -> Vec<f64> {
+    if players.len() < 2 {
+        return players.iter().map(|(rating, _)| *rating).collect();
+    }
+
+    // A broken rating would spread NaN to everyone in the game
+    let players: Vec<_> = players.iter()
+        .map(|(rating, placement)| (if rating.is_finite() { *rating } else { DEFAULT_RATING }, *placement))
+        .collect();
+    let k = K_FACTOR / (players.len() - 1) as f64;
+    players.iter().enumerate().map(|(i, (rating, placement))| {
+        let delta: f64 = players.iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, (other_rating, other_placement))| {
+                let actual = if placement < other_placement {
+                    1.0
+                } else if placement == other_placement {
+                    0.5
+                } else {
+                    0.0
+                };
+                actual - expected_score(*rating, *other_rating)
+            })
+            .sum();
+
+        rating + k * delta
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn a_duel_between_equals_moves_half_the_k_factor() {
+        let ratings = update_ratings(&[(1500.0, 1), (1500.0, 2)]);
+        assert_close(ratings[0], 1516.0);
+        assert_close(ratings[1], 1484.0);
+    }
+
+    #[test]
+    fn a_draw_between_equals_changes_nothing() {
+        assert_eq!(update_ratings(&[(1500.0, 1), (1500.0, 1)]), vec![1500.0, 1500.0]);
+    }
+
+    #[test]
+    fn an_upset_moves_more_than_the_expected_result() {
+        let upset = update_ratings(&[(1300.0, 1), (1700.0, 2)]);
+        let expected = update_ratings(&[(1700.0, 1), (1300.0, 2)]);
+        assert!(upset[0] - 1300.0 > expected[0] - 1700.0);
+    }
+
+    #[test]
+    fn free_for_all_keeps_the_rating_total() {
+        let players = [(1400.0, 3), (1550.0, 1), (1600.0, 4), (1450.0, 2)];
+        let ratings = update_ratings(&players);
+        assert_close(ratings.iter().sum(), players.iter().map(|(rating, _)| rating).sum());
+        assert!(ratings[1] > 1550.0);
+        assert!(ratings[2] < 1600.0);
+    }
+
+    #[test]
+    fn a_lone_player_keeps_their_rating() {
+        assert_eq!(update_ratings(&[(1234.0, 1)]), vec![1234.0]);
+    }
+
+    #[test]
+    fn broken_ratings_count_as_the_default() {
+        let ratings = update_ratings(&[(f64::NAN, 1), (1500.0, 2)]);
+        assert_close(ratings[0], 1516.0);
+        assert_close(ratings[1], 1484.0);
+    }
+}
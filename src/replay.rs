@@ -0,0 +1,29 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Mutation;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum ReplayEvent {
+    Mutation { turn: usize, mutation: Mutation },
+    PlayerDied { turn: usize, user_id: usize, killer_id: Option<usize> },
+    Chat { turn: usize, user_id: usize, text: String },
+}
+
+fn replay_dir() -> PathBuf {
+    PathBuf::from(env::var("LD47_REPLAY_DIR").unwrap_or_else(|_| String::from("replays")))
+}
+
+/// Writes the replay log of a finished game to `LD47_REPLAY_DIR/<game_id>.json`
+pub fn save(game_id: usize, events: &[ReplayEvent]) -> io::Result<()> {
+    let dir = replay_dir();
+    fs::create_dir_all(&dir)?;
+
+    let json_string = serde_json::to_string(events)?;
+    fs::write(dir.join(format!("{}.json", game_id)), json_string)
+}
@@ -1,15 +1,43 @@
+use std::cmp::Ordering;
 use std::time::{Duration, Instant};
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
 use actix::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use rand::prelude::*;
 use rand::Rng;
 use rand::distributions::WeightedIndex;
-use crate::models::{Game, Player, Mutation, CardOptions};
-use crate::config::{Card, Animation, GameConfig};
-
-const MAX_PLAYERS: usize = 10;
-const BOARD_SIZE: (u16, u16) = (16, 9);
+use crate::models::{Game, Player, NewPlayer, Mutation, CardOptions, CardUsage, GameOver, Bounds, Deck, StatusKind, Cosmetics};
+use crate::config::{Card, Animation, BotDifficulty, CosmeticSettings, DeckSettings, GameConfig, GameMode, LobbySettings, QueueType};
+use crate::storage::{SharedStorage, StorageError};
+use crate::rating::{self, DEFAULT_RATING};
+use crate::validation::unique_username;
+use crate::replay::{self, ReplayEvent};
+use crate::simulator::{SimEvent, Simulator};
+use crate::cards::CardCatalogue;
+use crate::map::MapDefinition;
+use crate::snapshot;
+
+pub const MAX_PLAYERS: usize = 10;
+pub const BOARD_SIZE: (u16, u16) = (16, 9);
+const MAX_RECENT_RESULTS: usize = 50;
+const MAX_CHAT_LEN: usize = 200;
+/// At most `CHAT_RATE_LIMIT` messages per player every `CHAT_RATE_WINDOW`
+const CHAT_RATE_LIMIT: usize = 5;
+const CHAT_RATE_WINDOW: Duration = Duration::from_secs(10);
+const EMOTE_COOLDOWN: Duration = Duration::from_millis(1500);
+/// Range the host can set the lobby countdown to
+const MIN_COUNTDOWN_SECS: u64 = 5;
+const MAX_COUNTDOWN_SECS: u64 = 120;
+/// Largest decks the host can ask for
+const MAX_HAND_SIZE: usize = 10;
+const MAX_DECK_COPIES: usize = 5;
+/// Time running games get to finish when the server is asked to shut down
+pub const DEFAULT_DRAIN_SECS: u64 = 300;
+const MAX_DRAIN_SECS: u64 = 24 * 60 * 60;
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+/// Time humans get to rejoin a game restored when the process starts, before they are counted as gone
+const REJOIN_GRACE: Duration = Duration::from_secs(60);
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -20,8 +48,28 @@ pub struct ToUserMessage(pub String);
 pub struct Connect {
     pub username: String,
     pub character_type: u8,
+    pub team: Option<u8>,
+    pub cosmetics: CosmeticChoice,
+    pub account_token: Option<String>,
     pub addr: Option<Recipient<ToUserMessage>>,
-    pub game_id: Option<usize>, // Only for bot use
+    pub game_id: Option<usize>, // Only for bot and matchmaker use
+}
+
+/// The looks a player asks for, anything that isn't available is replaced when they join
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CosmeticChoice {
+    pub color: Option<u8>,
+    pub hat: Option<u8>,
+    pub skin: Option<u8>,
+}
+
+/// Creates a lobby for a group of players formed by the matchmaker
+#[derive(Message)]
+#[rtype(usize)]
+pub struct CreateLobby {
+    pub mode: GameMode,
+    pub queue: QueueType,
+    pub players: Vec<Connect>,
 }
 
 #[derive(Message)]
@@ -66,6 +114,115 @@ pub struct MessageFullClean {
     pub addr: Recipient<ToUserMessage>,
 }
 
+#[derive(Message)]
+#[rtype(usize)]
+pub struct ChatMessage {
+    pub text: String,
+    pub player_id: usize,
+    pub pk: String,
+    pub game_id: usize,
+}
+
+#[derive(Message)]
+#[rtype(usize)]
+pub struct ReadyMessage {
+    pub ready: bool,
+    pub player_id: usize,
+    pub pk: String,
+    pub game_id: usize,
+}
+
+#[derive(Message)]
+#[rtype(usize)]
+pub struct MapVoteMessage {
+    pub map_index: usize,
+    pub player_id: usize,
+    pub pk: String,
+    pub game_id: usize,
+}
+
+#[derive(Message)]
+#[rtype(usize)]
+pub struct EmoteMessage {
+    pub emote_id: u8,
+    pub tile: Option<(u16, u16)>,
+    pub player_id: usize,
+    pub pk: String,
+    pub game_id: usize,
+}
+
+/// Lobby settings only the host can change, and only before the game starts
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command")]
+pub enum HostCommand {
+    SetMode { mode: GameMode },
+    SetMap { map: String },
+    SetBotDifficulty { difficulty: BotDifficulty },
+    SetTimers { countdown_secs: u64, ready_countdown_secs: u64 },
+    /// `None` goes back to sampling every turn's options
+    SetDeck { deck: Option<DeckSettings> },
+    Kick { target_id: usize },
+    StartNow,
+}
+
+#[derive(Message)]
+#[rtype(usize)]
+pub struct HostCommandMessage {
+    pub command: HostCommand,
+    pub player_id: usize,
+    pub pk: String,
+    pub game_id: usize,
+}
+
+/// Hides (or shows again) the chat of another player for this player only
+#[derive(Message)]
+#[rtype(usize)]
+pub struct MutePlayerMessage {
+    pub target_id: usize,
+    pub muted: bool,
+    pub player_id: usize,
+    pub pk: String,
+    pub game_id: usize,
+}
+
+#[derive(Message)]
+#[rtype(usize)]
+pub struct MessageAdminMute {
+    pub game_id: usize,
+    pub player_id: usize,
+    pub muted: bool,
+    pub addr: Recipient<ToUserMessage>,
+}
+
+#[derive(Message)]
+#[rtype(usize)]
+pub struct MessageStats {
+    pub game_id: Option<usize>,
+    pub addr: Recipient<ToUserMessage>,
+}
+
+/// Closes the lobbies and stops new games, running games get `secs` to finish
+#[derive(Message)]
+#[rtype(usize)]
+pub struct Drain {
+    pub secs: u64,
+}
+
+/// Whether a drain has finished and the process can exit
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct DrainStatus;
+
+/// Puts a human back into a game restored from a snapshot, they prove it's them with their private key
+#[derive(Message)]
+#[rtype(usize)]
+pub struct RejoinMessage {
+    pub player_id: usize,
+    pub pk: String,
+    pub game_id: usize,
+    pub addr: Recipient<ToUserMessage>,
+}
+
 /// Session is disconnected
 #[derive(Message)]
 #[rtype(i32)]
@@ -81,7 +238,9 @@ pub struct PlayerJoin {
     pub x: u16,
     pub y: u16,
     pub char_type: u8,
+    pub team: Option<u8>,
     pub start_orientation: u8,
+    pub cosmetics: Cosmetics,
 }
 
 #[derive(Message)]
@@ -115,16 +274,6 @@ pub struct GameTickerMessage {
     pub game_id: usize,
 }
 
-#[derive(Message)]
-#[rtype(usize)]
-pub struct PollPlayerDeathMessage {
-    pub other_id: usize,
-    pub self_id: usize,
-    pub pk: String,
-    pub game_id: usize,
-    pub turn_id: usize,
-}
-
 #[derive(Message)]
 #[rtype(usize)]
 pub struct DeleteGame {
@@ -140,7 +289,130 @@ impl PlayerJoin {
             x: player.pos.0,
             y: player.pos.1,
             char_type: player.character_type,
+            team: player.team,
             start_orientation: player.pos.2,
+            cosmetics: player.cosmetics,
+        }
+    }
+}
+
+/// Sent to every player when the server starts draining, lobbies are closed right away so they get `secs: 0`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct ServerShuttingDown {
+    pub secs: u64,
+}
+
+/// Where the playable area of a `GameMode::Loop` game is, players outside it take `ring_damage`
+/// at the start of every turn
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct BoardShrink {
+    pub bounds: Bounds,
+    pub turns_until_next: Option<usize>,
+    pub ring_damage: Option<u8>,
+}
+
+/// What the ring did to the players left outside it at the start of a turn
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct RingDamage {
+    pub turn_id: usize,
+    pub events: Vec<SimEvent>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct MapInfo {
+    pub map: MapDefinition,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct MapVoteOptions {
+    pub maps: Vec<String>,
+    pub votes: Vec<usize>,
+}
+
+/// Announces the map a game is played on once the countdown ends, with the players' final spawns
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct MapChosen {
+    pub map: MapDefinition,
+    pub players: Vec<PlayerJoin>,
+}
+
+/// Sent to a player when they join so the client simulates the same rules
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct GameRules {
+    pub mode: GameMode,
+    pub queue: QueueType,
+    pub team_count: Option<u8>,
+    pub friendly_fire: bool,
+    pub palette: Vec<String>,
+}
+
+/// Every programmed card of a turn in the order they resolve, sent once all programs are complete
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct TurnResolution {
+    pub turn_id: usize,
+    pub steps: Vec<Mutation>,
+    pub events: Vec<SimEvent>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveEffect {
+    pub kind: StatusKind,
+    /// Turns the effect lasts after the current one
+    pub turns_left: usize,
+}
+
+/// Every player's active status effects, players that aren't listed have none
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct StatusEffects {
+    pub turn_id: usize,
+    pub players: HashMap<usize, Vec<ActiveEffect>>,
+}
+
+impl StatusEffects {
+    fn from(game: &Game) -> Self {
+        StatusEffects {
+            turn_id: game.turn_index,
+            players: game.players.iter()
+                .filter(|p| p.active && !p.status_effects.is_empty())
+                .map(|p| (p.id, p.status_effects.iter().map(|effect| ActiveEffect {
+                    kind: effect.kind,
+                    turns_left: effect.expires_after_turn.saturating_sub(game.turn_index),
+                }).collect()))
+                .collect(),
+        }
+    }
+}
+
+/// Sent to everyone in a lobby whenever the host, its settings or who is ready changes
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct LobbyState {
+    pub host_id: Option<usize>,
+    pub mode: GameMode,
+    pub settings: LobbySettings,
+    pub map: String,
+    pub ready: Vec<usize>,
+    pub players: Vec<PlayerJoin>,
+}
+
+impl LobbyState {
+    fn from(game: &Game) -> Self {
+        LobbyState {
+            host_id: game.host_id,
+            mode: game.mode,
+            settings: game.settings.clone(),
+            map: game.map.name.clone(),
+            ready: game.players.iter().filter(|p| p.ready).map(|p| p.id).collect(),
+            players: game.seated_players().map(PlayerJoin::from).collect(),
         }
     }
 }
@@ -151,57 +423,194 @@ pub struct PlayerDied {
     pub user_id: usize,
 }
 
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct PlayerEmote {
+    pub user_id: usize,
+    pub emote_id: u8,
+    pub emote: String,
+    pub tile: Option<(u16, u16)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct PlayerChat {
+    pub user_id: usize,
+    pub username: String,
+    pub text: String,
+}
+
 pub struct GameServer {
     games: HashMap<usize, Game>,
     rng: ThreadRng,
-    cardDist: WeightedIndex<f64>,
+    /// Card distribution of every character type
+    card_dists: Vec<WeightedIndex<f64>>,
     gc: GameConfig,
+    recent_results: VecDeque<GameOver>,
+    storage: SharedStorage,
+    maps: Vec<MapDefinition>,
+    rotation_index: HashMap<GameMode, usize>,
+    /// Set once the server is draining
+    drain_deadline: Option<Instant>,
+    drained: bool,
+    /// Games with a snapshot on disk and the hash of what was last written, `None` for snapshots
+    /// loaded at startup
+    snapshotted: HashMap<usize, Option<u64>>,
+    snapshot_dir: PathBuf,
 }
 
 impl GameServer {
-    pub fn new() -> GameServer {
+    fn shutdown_notice(&self) -> ServerShuttingDown {
+        ServerShuttingDown {
+            secs: self.drain_deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()).as_secs()).unwrap_or(0),
+        }
+    }
+
+    /// Tells a player that tried to join a draining server that no games are starting
+    fn reject_draining(&self, addr: &Option<Recipient<ToUserMessage>>) {
+        if let Some(ref addr) = addr {
+            if let Ok(json_string) = serde_json::to_string(&self.shutdown_notice()) {
+                let _ = addr.do_send(ToUserMessage(json_string));
+            }
+        }
+    }
+
+    /// Ends the drain once every game is over or the deadline has passed, the replays of games
+    /// that didn't finish are saved as they are
+    fn check_drain(&mut self, ctx: &mut Context<Self>) {
+        let deadline = match self.drain_deadline {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        if self.drained || (!self.games.is_empty() && Instant::now() < deadline) {
+            return;
+        }
+
+        self.save_snapshots();
+
+        for (game_id, game) in self.games.drain() {
+            ctx.cancel_future(game.game_tick_handle);
+            Self::broadcast_to_game(&ServerShuttingDown { secs: 0 }, None, game.get_cloned_players_id_addr());
+            if game.game_started {
+                println!("Game {} didn't finish before shutdown", game_id);
+                if let Err(err) = replay::save(game_id, &game.replay_log) {
+                    println!("Failed to save replay of game {}: {}", game_id, err);
+                }
+            }
+        }
+        self.drained = true;
+        println!("Drain finished");
+    }
+
+    pub fn new(storage: SharedStorage, maps: Vec<MapDefinition>, catalogue: CardCatalogue) -> GameServer {
+        let gc = GameConfig {
+            cards: catalogue.cards,
+            actions: catalogue.actions,
+            ..GameConfig::default()
+        };
+        // `pick_cosmetics` needs a color of its own for every player in a lobby
+        assert!(
+            gc.cosmetics.palette.len() >= gc.lobby.max_players.max(gc.ranked_lobby.max_players),
+            "The palette has fewer colors than a lobby has players"
+        );
+
+        let snapshot_dir = snapshot::snapshot_dir();
+        let mut games = HashMap::new();
+        for snapshot in snapshot::load_all(&snapshot_dir) {
+            // Nobody is left in a lobby after a restart, the players of running games can rejoin
+            if !snapshot.game_started {
+                if let Err(err) = snapshot::remove(&snapshot_dir, snapshot.game_id) {
+                    println!("Failed to remove snapshot of lobby {}: {}", snapshot.game_id, err);
+                }
+                continue;
+            }
+
+            let game_id = snapshot.game_id;
+            let mut game = snapshot.restore();
+            game.rejoin_deadline = Some(Instant::now() + REJOIN_GRACE);
+            games.insert(game_id, game);
+        }
+        if !games.is_empty() {
+            println!("Restored {} games", games.len());
+        }
+
         GameServer {
-            games: HashMap::new(),
+            snapshotted: games.keys().map(|game_id| (*game_id, None)).collect(),
+            games: games,
             rng: rand::thread_rng(),
-            cardDist: WeightedIndex::new(vec![15f64,
-                15f64,
-                5f64,
-                10f64,
-                15f64,
-                15f64,
-                5f64,
-                0f64,
-                0f64,
-                3.6f64,
-                3.6f64,
-                3.6f64,
-                3.6f64,
-                3.6f64,
-                3.6f64,
-                3.6f64,
-                2.5f64,
-                2.5f64,
-                3.6f64,
-                3.6f64,
-                3.6f64,
-                3.6f64,
-                2.3f64,
-                2.3f64,
-                2.3f64,
-                2.3f64,
-                3.6f64,
-                3.6f64,
-                2.0f64,
-                2.0f64,
-                2.0f64,
-                2.0f64,
-                2.0f64,
-                1.2f64,
-                2.0f64,
-                2.0f64,
-                2.0f64,
-                1.2f64]).unwrap(),
-            gc: GameConfig::default(),
+            card_dists: (0 .. gc.characters.len())
+                .map(|character_type| WeightedIndex::new(gc.character_card_weights(character_type as u8)).unwrap())
+                .collect(),
+            gc: gc,
+            recent_results: VecDeque::new(),
+            storage: storage,
+            maps: maps,
+            rotation_index: HashMap::new(),
+            drain_deadline: None,
+            drained: false,
+            snapshot_dir: snapshot_dir,
+        }
+    }
+
+    fn start_game_tick(game_id: usize, ctx: &mut Context<Self>) -> SpawnHandle {
+        ctx.run_interval(Duration::from_millis(1000), move |_, ctx| {
+            ctx.address().do_send(GameTickerMessage {
+                game_id: game_id,
+            });
+        })
+    }
+
+    /// Starts the timers of a game again, lobbies count down from the start and bots program the current turn
+    fn resume_game(&mut self, game_id: usize, ctx: &mut Context<Self>) {
+        let current_game = match self.games.get_mut(&game_id) {
+            Some(current_game) => current_game,
+            None => return,
+        };
+
+        current_game.game_tick_handle = Self::start_game_tick(game_id, ctx);
+        current_game.game_countdown_handle = None;
+        current_game.has_loop_countdown = false;
+        if !current_game.game_started {
+            ctx.address().do_send(CountDownMessage {
+                game_id: game_id,
+            });
+            return;
+        }
+
+        for bot in current_game.players.iter().filter(|p| p.is_ai && p.active && !p.program_complete()) {
+            Self::program_bot(
+                ctx,
+                bot,
+                current_game.turn_index,
+                &self.card_dists[bot.character_type as usize],
+                current_game.settings.bot_difficulty,
+                &self.gc,
+                &mut self.rng
+            );
+        }
+    }
+
+    /// Writes every game that changed to disk and removes the snapshots of games that are over
+    fn save_snapshots(&mut self) {
+        // Games left after a drain are kept on disk to carry on after the restart
+        if self.drained {
+            return;
+        }
+
+        for (game_id, game) in self.games.iter() {
+            match snapshot::save(&self.snapshot_dir, *game_id, game, self.snapshotted.get(game_id).cloned().flatten()) {
+                Ok(hash) => { self.snapshotted.insert(*game_id, Some(hash)); },
+                Err(err) => println!("Failed to snapshot game {}: {}", game_id, err),
+            }
+        }
+
+        let games = &self.games;
+        let finished: Vec<_> = self.snapshotted.keys().filter(|game_id| !games.contains_key(game_id)).cloned().collect();
+        for game_id in finished {
+            match snapshot::remove(&self.snapshot_dir, game_id) {
+                Ok(()) => { self.snapshotted.remove(&game_id); },
+                Err(err) => println!("Failed to remove snapshot of game {}: {}", game_id, err),
+            }
         }
     }
 
@@ -231,17 +640,260 @@ impl GameServer {
         }
     }
 
+    /// Returns the next maps in the mode's rotation and advances it, the first one is played if nobody votes
+    fn next_maps(&mut self, mode: GameMode) -> Vec<MapDefinition> {
+        let maps = &self.maps;
+        let rotation: Vec<&MapDefinition> = match self.gc.map_rotation.get(&mode) {
+            Some(names) => names.iter().filter_map(|name| maps.iter().find(|map| &map.name == name)).collect(),
+            None => maps.iter().collect(),
+        };
+
+        if rotation.is_empty() {
+            return vec![MapDefinition::open(BOARD_SIZE)];
+        }
+
+        let index = self.rotation_index.entry(mode).or_insert(0);
+        let count = self.gc.map_vote_options.max(1).min(rotation.len());
+        let next = (0 .. count).map(|i| rotation[(*index + i) % rotation.len()].clone()).collect();
+        *index = (*index + 1) % rotation.len();
+
+        next
+    }
+
+    pub fn spawn_positions<R: Rng>(map: &MapDefinition, max_players: usize, rng: &mut R) -> Vec<(u16, u16)> {
+        let mut available_pos = if map.spawn_points.is_empty() {
+            map.random_spawn_tiles()
+        } else {
+            let mut spawn_points = map.spawn_points.clone();
+            spawn_points.sort();
+            spawn_points.dedup();
+            spawn_points
+        };
+        available_pos.shuffle(rng);
+        available_pos.truncate(max_players);
+
+        available_pos
+    }
+
+    /// Switches the lobby to the map with the most votes (earliest in the rotation on a tie),
+    /// moving the players already in it onto the new map's spawn points
+    fn apply_map_vote(game: &mut Game, rng: &mut ThreadRng) {
+        let counts = game.map_vote_counts();
+        let winner = (0 .. counts.len()).rev().max_by_key(|i| counts[*i]).unwrap_or(0);
+
+        if game.map_candidates[winner].name != game.map.name {
+            game.map = game.map_candidates[winner].clone();
+            game.board_size = game.map.board_size;
+            game.bounds = Bounds::from_board_size(game.map.board_size);
+
+            let mut available_pos = Self::spawn_positions(&game.map, game.settings.max_players, rng);
+            for player in game.players.iter_mut().filter(|p| !p.kicked) {
+                if !available_pos.is_empty() {
+                    let pos = available_pos.remove(0);
+                    player.pos = (pos.0, pos.1, player.pos.2);
+                }
+            }
+            game.available_pos = available_pos;
+        }
+
+        Self::broadcast_to_game(&MapChosen {
+            map: game.map.clone(),
+            players: game.seated_players().map(PlayerJoin::from).collect(),
+        }, None, game.get_cloned_players_id_addr());
+    }
+
+    /// Creates an empty lobby and starts its countdown, returning the game id
+    fn create_game(&mut self, mode: GameMode, queue: QueueType, ctx: &mut Context<Self>) -> usize {
+        let mut key: u32 = self.rng.gen();
+        while self.games.contains_key(&(key as usize)) { key = self.rng.gen(); }
+
+        let map_candidates = self.next_maps(mode);
+        let settings = self.gc.lobby_settings(queue).clone();
+        let available_pos = Self::spawn_positions(&map_candidates[0], settings.max_players, &mut self.rng);
+
+        let game_tick_handle = Self::start_game_tick(key as usize, ctx);
+        self.games.insert(key as usize, Game::new(
+            mode,
+            queue,
+            settings,
+            map_candidates,
+            available_pos,
+            game_tick_handle
+        ));
+
+        ctx.address().do_send(CountDownMessage {
+            game_id: key as usize,
+        });
+
+        key as usize
+    }
+
     fn fill_slots_with_ai(game: &mut Game, rng: &mut ThreadRng, ctx: &mut Context<Self>, game_id: usize, gc: &GameConfig) {
-        for n in 0 .. (MAX_PLAYERS - game.players.len()) {
+        let target_players = if game.settings.fill_with_bots { game.settings.max_players } else { game.settings.min_players };
+
+        for n in 0 .. target_players.saturating_sub(game.seated_players().count()) {
             ctx.address().do_send(Connect {
                 username: format!("Bot {}", n),
-                character_type: rng.gen_range(0, 3),
+                character_type: rng.gen_range(0, gc.characters.len()) as u8,
+                team: None,
+                cosmetics: CosmeticChoice::default(),
+                account_token: None,
                 addr: None,
                 game_id: Some(game_id),
             });
         }
     }
 
+    /// Rates every player in the game by placement and stores the ratings of those with an account
+    fn update_ratings(storage: &SharedStorage, game: &Game, game_over: &GameOver) -> Result<(), StorageError> {
+        let placements: Vec<_> = game_over.players.iter()
+            .map(|result| (game.players[result.user_id].rating, result.placement))
+            .collect();
+        let new_ratings = rating::update_ratings(&placements);
+
+        let account_ratings: Vec<_> = game_over.players.iter()
+            .zip(new_ratings)
+            .filter_map(|(result, new_rating)| game.players[result.user_id].account_id.map(|id| (id, new_rating)))
+            .collect();
+
+        storage.lock().unwrap().update_ratings(&account_ratings)
+    }
+
+    /// Tells the players what the ring did to them and where it is, when it just contracted or the game started
+    fn announce_ring(game: &Game, gc: &GameConfig, events: Vec<SimEvent>) {
+        let addr_data = game.get_cloned_players_id_addr();
+        if !events.is_empty() {
+            Self::broadcast_to_game(&RingDamage {
+                turn_id: game.turn_index,
+                events: events.clone(),
+            }, None, addr_data.clone());
+            for event in events {
+                if let SimEvent::Died { user_id, .. } = event {
+                    Self::broadcast_to_game(&PlayerDied { user_id: user_id }, None, addr_data.clone());
+                }
+            }
+        }
+
+        if game.ring_shrinks_now(gc) || game.turn_index == 0 {
+            let can_shrink = gc.shrink_every_turns > 0 && game.bounds.shrink(gc.min_ring_size) != game.bounds;
+            Self::broadcast_to_game(&BoardShrink {
+                bounds: game.bounds,
+                turns_until_next: if can_shrink { Some(gc.shrink_every_turns) } else { None },
+                ring_damage: gc.ring_damage,
+            }, None, addr_data);
+        }
+    }
+
+    /// Puts the player in the requested team if it has room, otherwise in the smallest team
+    fn pick_team(game: &Game, requested: Option<u8>, team_count: u8) -> u8 {
+        let team_size = |team: u8| game.seated_players().filter(|p| p.team == Some(team)).count();
+        let max_team_size = game.settings.max_players.div_ceil(team_count as usize);
+
+        match requested {
+            Some(team) if team < team_count && team_size(team) < max_team_size => team,
+            _ => (0 .. team_count).min_by_key(|team| team_size(*team)).unwrap_or(0),
+        }
+    }
+
+    /// Keeps the requested color unless it's taken or not in the palette, then the first free one is used
+    fn pick_cosmetics(game: &Game, requested: CosmeticChoice, settings: &CosmeticSettings) -> Cosmetics {
+        let taken = |color: u8| game.seated_players().any(|p| p.cosmetics.color == color);
+        let color = match requested.color {
+            Some(color) if (color as usize) < settings.palette.len() && !taken(color) => color,
+            _ => (0 .. settings.palette.len() as u8).find(|color| !taken(*color)).unwrap_or(0),
+        };
+
+        Cosmetics {
+            color: color,
+            hat: requested.hat.filter(|hat| *hat < settings.hats),
+            skin: requested.skin.filter(|skin| *skin < settings.skins),
+        }
+    }
+
+    /// Card a bot plays this turn, harder bots draw more cards and keep the rarest
+    fn bot_card(card_dist: &WeightedIndex<f64>, card_weights: &[f64], difficulty: BotDifficulty, rng: &mut ThreadRng) -> u8 {
+        (0 .. difficulty.draws())
+            .map(|_| card_dist.sample(rng))
+            .min_by(|a, b| card_weights[*a].partial_cmp(&card_weights[*b]).unwrap())
+            .unwrap_or(0) as u8
+    }
+
+    /// Every card of a fresh deck, the number of copies of a card follows its weight
+    fn deck_cards(card_weights: &[f64], max_copies: usize) -> Vec<u8> {
+        let max_weight = card_weights.iter().cloned().fold(0f64, f64::max);
+        let mut cards = Vec::new();
+        for (card, weight) in card_weights.iter().enumerate().filter(|(_, weight)| **weight > 0f64) {
+            let copies = ((weight / max_weight * max_copies as f64).round() as usize).max(1);
+            cards.extend(std::iter::repeat_n(card as u8, copies));
+        }
+
+        cards
+    }
+
+    /// Puts a card into a slot of a player's program and shows it to the game
+    fn program_card(game: &mut Game, player_id: usize, mutation: Mutation) {
+        game.replay_log.push(ReplayEvent::Mutation {
+            turn: game.turn_index,
+            mutation: mutation.clone(),
+        });
+
+        let player = &mut game.players[player_id];
+        player.program[mutation.card_location as usize] = Some(mutation.clone());
+        *player.stats.cards_played.entry(mutation.card_type).or_insert(0) += 1;
+        if let Some(ref mut deck) = player.deck {
+            deck.discard(mutation.card_type);
+        }
+        player.previous_choices.push(mutation.clone());
+
+        Self::broadcast_to_game(&mutation, None, game.get_cloned_players_id_addr());
+    }
+
+    /// Plays out the cards programmed this turn and shows the players what happened
+    fn play_turn(game: &mut Game, gc: &GameConfig, rng: &mut ThreadRng) {
+        let steps = Simulator::resolution_order(&game.players, gc);
+        let events = Simulator::resolve_turn(game, &steps, gc, rng);
+        game.turn_resolved = true;
+        let addr_data = game.get_cloned_players_id_addr();
+
+        Self::broadcast_to_game(&TurnResolution {
+            turn_id: game.turn_index,
+            steps: steps,
+            events: events.clone(),
+        }, None, addr_data.clone());
+        for event in events {
+            if let SimEvent::Died { user_id, .. } = event {
+                Self::broadcast_to_game(&PlayerDied { user_id: user_id }, None, addr_data.clone());
+            }
+        }
+        Self::broadcast_to_game(&StatusEffects::from(game), None, addr_data);
+    }
+
+    /// Queues a card for every program slot of a bot
+    fn program_bot(
+        ctx: &mut Context<Self>,
+        bot: &Player,
+        turn_id: usize,
+        card_dist: &WeightedIndex<f64>,
+        difficulty: BotDifficulty,
+        gc: &GameConfig,
+        rng: &mut ThreadRng
+    ) {
+        let card_weights = gc.character_card_weights(bot.character_type);
+        for slot in 0 .. gc.program_slots {
+            ctx.address().do_send(MutationMessage {
+                mutation: Mutation {
+                    user_id: bot.id,
+                    card_type: Self::bot_card(card_dist, &card_weights, difficulty, rng),
+                    card_location: slot as u8,
+                },
+                player_id: bot.id,
+                pk: bot.private_key.clone(),
+                game_id: bot.game_id,
+                turn_id: turn_id,
+            });
+        }
+    }
+
     fn get_real_active_players(game: &Game) -> Vec<&Player> {
         game.players.iter().filter(|p| !p.is_ai && p.active).collect()
     }
@@ -256,10 +908,25 @@ impl Actor for GameServer {
     /// We are going to use simple Context, we just need ability to communicate
     /// with other actors.
     type Context = Context<Self>;
+
+    /// Also runs after a restart, the games carry on with new timers
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let game_ids: Vec<_> = self.games.keys().cloned().collect();
+        for game_id in game_ids {
+            self.resume_game(game_id, ctx);
+        }
+
+        ctx.run_interval(SNAPSHOT_INTERVAL, |act, _| act.save_snapshots());
+        if self.drain_deadline.is_some() {
+            ctx.run_interval(Duration::from_secs(1), |act, ctx| act.check_drain(ctx));
+        }
+    }
 }
 
 impl actix::Supervised for GameServer {
-    fn restarting(&mut self, ctx: &mut Context<GameServer>) {
+    /// The games stay as they are in memory, so the players' clients stay in step with them. Their
+    /// timers stopped with the old context and `started` runs them again
+    fn restarting(&mut self, _: &mut Context<GameServer>) {
         println!("restarting server after error");
     }
 }
@@ -268,15 +935,52 @@ impl Handler<Connect> for GameServer {
     type Result = usize;
 
     fn handle(&mut self, connect: Connect, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain_deadline.is_some() {
+            self.reject_draining(&connect.addr);
+            return 0;
+        }
+
         let current_game: &mut Game;
-        let mut key: u32;
+        let key: u32;
         let mut open_games: Vec<(_, &mut Game)>;
 
+        let account = match connect.account_token {
+            Some(ref token) => match self.storage.lock().unwrap().find_account(token) {
+                Ok(account) => account,
+                Err(err) => {
+                    println!("{}", err);
+                    None
+                },
+            },
+            None => None,
+        };
+        let account_id = account.as_ref().map(|a| a.id);
+        let player_rating = account.as_ref().map(|a| a.rating).unwrap_or(DEFAULT_RATING);
+
         if let Some(ref game_id) = connect.game_id {
-            open_games = Vec::new();
-            open_games.push((game_id, self.games.get_mut(&game_id).unwrap()));
+            // The game can be gone (or, for humans, started) by the time a matchmaker or bot connect arrives
+            match self.games.get_mut(game_id) {
+                Some(game) if !game.game_started || connect.addr.is_none() => {
+                    open_games = Vec::new();
+                    open_games.push((game_id, game));
+                },
+                _ => {
+                    if let Some(ref addr) = connect.addr {
+                        let _ = addr.do_send(ToUserMessage(String::from("Game is no longer open")));
+                    }
+                    return 0;
+                },
+            }
         } else {
-            open_games = self.games.iter_mut().filter(|(_, game)| !game.game_started).collect();
+            // Ranked lobbies are only filled by the matchmaker
+            open_games = self.games.iter_mut()
+                .filter(|(_, game)| !game.game_started && game.queue == QueueType::Casual)
+                .collect();
+
+            if self.gc.prefer_similar_ratings {
+                let rating_gap = |game: &Game| (game.average_rating().unwrap_or(DEFAULT_RATING) - player_rating).abs();
+                open_games.sort_by(|(_, a), (_, b)| rating_gap(a).partial_cmp(&rating_gap(b)).unwrap_or(Ordering::Equal));
+            }
         }
 
         if open_games.len() > 0 {
@@ -284,70 +988,76 @@ impl Handler<Connect> for GameServer {
             current_game = open_games[0].1;
         } else {
             println!("didn't find open game");
-            key = self.rng.gen();
-            while self.games.contains_key(&(key as usize)) { key = self.rng.gen(); }
-
-            let mut available_pos = HashSet::new();
-            for _ in 0 .. MAX_PLAYERS {
-                let mut new_pos = (
-                    self.rng.gen_range(0, BOARD_SIZE.0),
-                    self.rng.gen_range(1, BOARD_SIZE.1)
-                );
-
-                while available_pos.contains(&new_pos) {
-                    new_pos = (
-                        self.rng.gen_range(0, BOARD_SIZE.0),
-                        self.rng.gen_range(1, BOARD_SIZE.1)
-                    );
-                }
-
-                available_pos.insert(new_pos);
-            }
-
-            let game_tick_handle = ctx.run_interval(Duration::from_millis(1000), move |act, ctx| {
-                ctx.address().do_send(GameTickerMessage {
-                    game_id: key as usize,
-                });  
-            }); 
-            self.games.insert(key as usize, Game {
-                board_size: BOARD_SIZE,
-                game_started: false,
-                players: Vec::new(),
-                turn_index: 0,
-                available_pos: available_pos.into_iter().collect(),
-                game_countdown_handle: None,
-                has_loop_countdown: false,
-                game_tick_handle: game_tick_handle,
-            });
-            current_game = self.games.get_mut(&(key as usize)).unwrap();
-
-            ctx.address().do_send(CountDownMessage {
-                game_id: key as usize,
-            });            
+            key = self.create_game(GameMode::FreeForAll, QueueType::Casual, ctx) as u32;
+            current_game = self.games.get_mut(&(key as usize)).unwrap();            
         }
 
         println!("{} joined", connect.username);
-        if !current_game.available_pos.is_empty() {
+        if !current_game.available_pos.is_empty() && current_game.seated_players().count() < current_game.settings.max_players {
             let pos = current_game.available_pos.remove(0);
             let player_id = current_game.players.len();
-            current_game.players.push(Player::new(
-                player_id,
-                key as usize,
+            let team = match current_game.mode {
+                GameMode::Teams => Some(Self::pick_team(current_game, connect.team, self.gc.team_count)),
+                GameMode::FreeForAll | GameMode::Loop => None,
+            };
+            let cosmetics = Self::pick_cosmetics(current_game, connect.cosmetics, &self.gc.cosmetics);
+            let character_type = if (connect.character_type as usize) < self.gc.characters.len() { connect.character_type } else { 0 };
+            let username = unique_username(
                 connect.username,
-                connect.character_type,
-                (pos.0, pos.1),
-                connect.addr.is_none(),
-                connect.addr.clone(),
-                &mut self.rng,
-            ));
+                current_game.players.iter().map(|p| p.username.as_str())
+            );
+            current_game.players.push(Player::new(NewPlayer {
+                id: player_id,
+                game_id: key as usize,
+                username: username,
+                character_type: character_type,
+                team: team,
+                pos: (pos.0, pos.1),
+                is_ai: connect.addr.is_none(),
+                health: self.gc.character(character_type).health,
+                cosmetics: cosmetics,
+                account_id: account_id,
+                rating: player_rating,
+                addr: connect.addr.clone(),
+            }, &mut self.rng));
+            // Ranked lobbies keep the default settings so they have no host
+            if current_game.host_id.is_none() && connect.addr.is_some() && current_game.queue == QueueType::Casual {
+                current_game.host_id = Some(player_id);
+            }
             let current_player = current_game.players.last().unwrap();
             if let Some(ref addr) = connect.addr {
                 if let Ok(json_string) = serde_json::to_string(&current_player) {
                     let _ = addr.do_send(ToUserMessage(json_string));
                 }
+
+                let rules = GameRules {
+                    mode: current_game.mode,
+                    queue: current_game.queue,
+                    team_count: if current_game.mode == GameMode::Teams { Some(self.gc.team_count) } else { None },
+                    friendly_fire: self.gc.friendly_fire,
+                    palette: self.gc.cosmetics.palette.clone(),
+                };
+                if let Ok(json_string) = serde_json::to_string(&rules) {
+                    let _ = addr.do_send(ToUserMessage(json_string));
+                }
+
+                let map_info = MapInfo { map: current_game.map.clone() };
+                if let Ok(json_string) = serde_json::to_string(&map_info) {
+                    let _ = addr.do_send(ToUserMessage(json_string));
+                }
+
+                if current_game.map_candidates.len() > 1 && !current_game.game_started {
+                    let vote_options = MapVoteOptions {
+                        maps: current_game.map_candidates.iter().map(|map| map.name.clone()).collect(),
+                        votes: current_game.map_vote_counts(),
+                    };
+                    if let Ok(json_string) = serde_json::to_string(&vote_options) {
+                        let _ = addr.do_send(ToUserMessage(json_string));
+                    }
+                }
             }
 
-            for some_player in current_game.players.iter().map(|p| PlayerJoin::from(p)) {
+            for some_player in current_game.seated_players().map(PlayerJoin::from) {
                 if let Ok(json_string) = serde_json::to_string(&some_player) {
                     if let Some(ref addr) = connect.addr {
                         let _ = addr.do_send(ToUserMessage(json_string));
@@ -359,9 +1069,12 @@ impl Handler<Connect> for GameServer {
                 Some(player_id),
                 current_game.get_cloned_players_id_addr()
             );
+            if !current_game.game_started {
+                Self::broadcast_to_game(&LobbyState::from(current_game), None, current_game.get_cloned_players_id_addr());
+            }
 
             // if max players has reached start game
-            if current_game.players.len() == MAX_PLAYERS && !current_game.game_started {
+            if current_game.seated_players().count() == current_game.settings.max_players && !current_game.game_started {
                 ctx.address().do_send(CreateTurnMessage {
                     game_id: key as usize,
                     check_turn_id: None,
@@ -369,17 +1082,15 @@ impl Handler<Connect> for GameServer {
             }
 
             if current_player.is_ai && current_player.active {
-                ctx.address().do_send(MutationMessage {
-                    mutation: Mutation {
-                        user_id: player_id,
-                        card_type: self.cardDist.sample(&mut self.rng) as u8,
-                        card_location: 0,
-                    },
-                    player_id: current_player.id,
-                    pk: current_player.private_key.clone(),
-                    game_id: current_player.game_id,
-                    turn_id: current_game.turn_index,
-                });
+                Self::program_bot(
+                    ctx,
+                    current_player,
+                    current_game.turn_index,
+                    &self.card_dists[current_player.character_type as usize],
+                    current_game.settings.bot_difficulty,
+                    &self.gc,
+                    &mut self.rng
+                );
             }
 
             player_id
@@ -392,29 +1103,71 @@ impl Handler<Connect> for GameServer {
     }
 }
 
+impl Handler<CreateLobby> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, lobby: CreateLobby, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain_deadline.is_some() {
+            for connect in lobby.players.iter() {
+                self.reject_draining(&connect.addr);
+            }
+            return 0;
+        }
+
+        let game_id = self.create_game(lobby.mode, lobby.queue, ctx);
+        println!("Created {} lobby {} for {} players", lobby.queue.name(), game_id, lobby.players.len());
+
+        for mut connect in lobby.players {
+            connect.game_id = Some(game_id);
+            ctx.address().do_send(connect);
+        }
+
+        game_id
+    }
+}
+
 impl Handler<MutationMessage> for GameServer {
     type Result = usize;
 
     fn handle(&mut self, mutation: MutationMessage, _: &mut Context<Self>) -> Self::Result {
         if let Some(current_game) = self.games.get_mut(&mutation.game_id) {
-            let addr_data = current_game.get_cloned_players_id_addr();
+            if mutation.turn_id != current_game.turn_index {
+                return mutation.player_id;
+            }
+
             let current_player = &mut current_game.players[mutation.player_id];
 
             if current_player.private_key == mutation.pk {
-                if let Some(ref card_options) = current_player.card_options {
-                    if card_options.contains(&mutation.mutation.card_type) || current_player.is_ai {
-                        current_player.previous_choices.push(mutation.mutation);
-                        Self::broadcast_to_game(
-                            current_player.previous_choices.last().unwrap(),
-                            None,
-                            addr_data
-                        );
+                let slot = mutation.mutation.card_location as usize;
+                let slot_open = current_player.program.get(slot).map(|s| s.is_none()).unwrap_or(false);
+
+                if let Some(ref mut card_options) = current_player.card_options {
+                    let option_index = card_options.iter().position(|c| *c == mutation.mutation.card_type);
+
+                    if !slot_open {
+                        if let Some(ref addr) = current_player.addr {
+                            let _ = addr.do_send(ToUserMessage(String::from("Slot not open")));
+                        }
+                    } else if option_index.is_some() || current_player.is_ai {
+                        // Each option can only fill one slot
+                        if let Some(option_index) = option_index {
+                            card_options.remove(option_index);
+                        }
+                        Self::program_card(current_game, mutation.player_id, mutation.mutation);
                     } else {
                         if let Some(ref addr) = current_player.addr {
                             let _ = addr.do_send(ToUserMessage(String::from("Card not in option")));
                         }
                     }
+                }
+
+                let current_player = &mut current_game.players[mutation.player_id];
+                if current_player.program_complete() {
                     current_player.card_options = None;
+
+                    if !current_game.turn_resolved && current_game.players.iter().filter(|p| p.active).all(|p| p.program_complete()) {
+                        Self::play_turn(current_game, &self.gc, &mut self.rng);
+                    }
                 }
             } else {
                 if let Some(ref addr) = current_player.addr {
@@ -430,14 +1183,32 @@ impl Handler<CountDownMessage> for GameServer {
     type Result = usize;
 
     fn handle(&mut self, countdown: CountDownMessage, ctx: &mut Context<Self>) -> Self::Result {
-        let start = Instant::now();
         let game_id = countdown.game_id;
         if let Some(current_game) = self.games.get_mut(&countdown.game_id) {
-            current_game.game_countdown_handle = Some(ctx.run_interval(Duration::from_secs(1), move |_act, ctx| {
-                let secs = Instant::now().duration_since(start);
-                if secs <= Duration::from_secs(30) {
+            current_game.countdown_deadline = Instant::now() + Duration::from_secs(current_game.settings.countdown_secs);
+            current_game.game_countdown_handle = Some(ctx.run_interval(Duration::from_secs(1), move |act, ctx| {
+                let current_game = match act.games.get_mut(&game_id) {
+                    Some(current_game) => current_game,
+                    None => return,
+                };
+
+                if current_game.queue == QueueType::Casual && current_game.ensure_host() {
+                    Self::broadcast_to_game(&LobbyState::from(current_game), None, current_game.get_cloned_players_id_addr());
+                }
+
+                let now = Instant::now();
+                let humans = current_game.connected_humans().len();
+                if now < current_game.countdown_deadline {
+                    ctx.address().do_send(BroadcastStr {
+                        json_string: format!("{{\"type\": \"TillStart\", \"secs\": \"{}\"}}", current_game.countdown_deadline.duration_since(now).as_secs()),
+                        ori_player_id: None,
+                        game_id: game_id
+                    });
+                } else if humans < current_game.settings.min_humans {
+                    current_game.countdown_deadline = now + Duration::from_secs(current_game.settings.countdown_secs);
+                    current_game.unready_deadline = None;
                     ctx.address().do_send(BroadcastStr {
-                        json_string: format!("{{\"type\": \"TillStart\", \"secs\": \"{}\"}}", 30 - secs.as_secs()),
+                        json_string: format!("{{\"type\": \"WaitingForPlayers\", \"humans\": {}, \"min_humans\": {}}}", humans, current_game.settings.min_humans),
                         ori_player_id: None,
                         game_id: game_id
                     });
@@ -479,6 +1250,7 @@ impl Handler<CreateTurnMessage> for GameServer {
     fn handle(&mut self, gameinfo: CreateTurnMessage, ctx: &mut Context<Self>) -> Self::Result {
         if let Some(current_game) = self.games.get_mut(&gameinfo.game_id) {
             let mut rng = self.rng;
+            let mut ring_events = Vec::new();
             current_game.has_loop_countdown = false;
 
             if let Some(check_turn_id) = gameinfo.check_turn_id {
@@ -489,31 +1261,40 @@ impl Handler<CreateTurnMessage> for GameServer {
                 ctx.cancel_future(countdown_handle);
                 current_game.game_countdown_handle = None;
                 current_game.game_started = true;
+                GameServer::apply_map_vote(current_game, &mut rng);
                 GameServer::fill_slots_with_ai(current_game, &mut rng, ctx, gameinfo.game_id, &self.gc);
             } else {
-                let active_players_with_no_move = current_game.players.iter().filter(|p| p.previous_choices.len() < current_game.turn_index && p.active);
-
-                for player in active_players_with_no_move {
-                    ctx.address().do_send(MutationMessage {
-                        mutation: Mutation {
-                            user_id: player.id,
-                            card_type: player.card_options.as_ref().unwrap()[self.rng.gen_range(0, 3)],
-                            card_location: 0,
-                        },
-                        player_id: player.id,
-                        pk: player.private_key.clone(),
-                        game_id: player.game_id,
-                        turn_id: current_game.turn_index,
-                    });
+                // Fill the empty slots with the options that are left and play the turn out before moving on
+                let players_with_no_move: Vec<_> = current_game.players.iter()
+                    .filter(|p| p.active && !p.program_complete())
+                    .map(|p| p.id)
+                    .collect();
+
+                for player_id in players_with_no_move {
+                    let player = &mut current_game.players[player_id];
+                    let mut card_options = player.card_options.take().unwrap_or_default();
+                    card_options.shuffle(&mut self.rng);
+                    let empty_slots: Vec<_> = player.program.iter().enumerate().filter(|(_, slot)| slot.is_none()).map(|(i, _)| i).collect();
+
+                    for (slot, card_type) in empty_slots.into_iter().zip(card_options) {
+                        Self::program_card(current_game, player_id, Mutation {
+                            user_id: player_id,
+                            card_type: card_type,
+                            card_location: slot as u8,
+                        });
+                    }
                 }
 
-                for player in current_game.players.iter_mut() {
-                    player.animation_done = false;
-                    player.has_death_voted = false;
-                    player.been_death_voted_for = 0;
+                if !current_game.turn_resolved {
+                    Self::play_turn(current_game, &self.gc, &mut self.rng);
                 }
 
-                current_game.turn_index += 1;
+                ring_events = current_game.advance_turn(&self.gc);
+                Self::broadcast_to_game(&StatusEffects::from(current_game), None, current_game.get_cloned_players_id_addr());
+            }
+
+            if current_game.mode == GameMode::Loop {
+                Self::announce_ring(current_game, &self.gc, ring_events);
             }
             
             ctx.address().do_send(CardChoiceMessage {
@@ -522,17 +1303,15 @@ impl Handler<CreateTurnMessage> for GameServer {
             });
 
             for ai_player in current_game.players.iter().filter(|p| p.is_ai && p.active) {
-                ctx.address().do_send(MutationMessage {
-                    mutation: Mutation {
-                        user_id: ai_player.id,
-                        card_type: self.cardDist.sample(&mut self.rng) as u8,
-                        card_location: 0,
-                    },
-                    player_id: ai_player.id,
-                    pk: ai_player.private_key.clone(),
-                    game_id: ai_player.game_id,
-                    turn_id: current_game.turn_index,
-                });
+                Self::program_bot(
+                    ctx,
+                    ai_player,
+                    current_game.turn_index,
+                    &self.card_dists[ai_player.character_type as usize],
+                    current_game.settings.bot_difficulty,
+                    &self.gc,
+                    &mut self.rng
+                );
             }
         }
 
@@ -545,16 +1324,43 @@ impl Handler<CardChoiceMessage> for GameServer {
 
     fn handle(&mut self, gameinfo: CardChoiceMessage, _: &mut Context<Self>) -> Self::Result {
         if let Some(current_game) = self.games.get_mut(&gameinfo.game_id) {
+            let options_per_turn = self.gc.options_per_turn.max(self.gc.program_slots);
             for player in  current_game.players.iter_mut() {
                 if player.active {
-                    player.card_options = Some(vec![
-                        self.cardDist.sample(&mut self.rng) as u8,
-                        self.cardDist.sample(&mut self.rng) as u8,
-                        self.cardDist.sample(&mut self.rng) as u8,
-                    ]); 
+                    player.program = vec![None; self.gc.program_slots];
+                    // Bots pick their own cards so they keep sampling even when humans play with decks
+                    let dealt = match current_game.settings.deck {
+                        Some(ref deck_settings) if !player.is_ai => {
+                            if player.deck.is_none() {
+                                let cards = Self::deck_cards(&self.gc.character_card_weights(player.character_type), deck_settings.max_copies);
+                                player.deck = Some(Deck::new(cards, &mut self.rng));
+                            }
+                            let deck = player.deck.as_mut().unwrap();
+                            Some(deck.deal_options(options_per_turn, deck_settings.hand_size, &mut self.rng))
+                        },
+                        _ => None,
+                    };
+
+                    // A deck without cards falls back to sampling
+                    match dealt {
+                        Some(card_options) if !card_options.is_empty() => player.card_options = Some(card_options),
+                        _ => {
+                            let mut card_options = Vec::new();
+                            for _ in 0 .. options_per_turn {
+                                card_options.push(self.card_dists[player.character_type as usize].sample(&mut self.rng) as u8);
+                            }
+                            player.card_options = Some(card_options);
+                        },
+                    }
+
+                    for card in player.card_options.as_ref().unwrap().iter() {
+                        *player.stats.cards_offered.entry(*card).or_insert(0) += 1;
+                    }
 
                     let card_options = CardOptions {
                         card_options: player.card_options.as_ref().unwrap().clone(),
+                        slots: self.gc.program_slots,
+                        hand: player.deck.as_ref().map(|deck| deck.hand.clone()),
                         player_id: player.id,
                         turn_id: gameinfo.turn_id,
                     };
@@ -573,9 +1379,12 @@ impl Handler<CardChoiceMessage> for GameServer {
 impl Handler<AnimationMessage> for GameServer {
     type Result = usize;
 
-    fn handle(&mut self, gameinfo: AnimationMessage, ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, gameinfo: AnimationMessage, _ctx: &mut Context<Self>) -> Self::Result {
         if let Some(current_game) = self.games.get_mut(&gameinfo.game_id) {
-            current_game.players[gameinfo.player_id].animation_done = true;
+            let player = &mut current_game.players[gameinfo.player_id];
+            if player.private_key == gameinfo.pk {
+                player.animation_done = true;
+            }
 
             // let players_with_animation_count = current_game
             //     .players
@@ -609,57 +1418,52 @@ impl Handler<AnimationMessage> for GameServer {
     }
 }
 
-impl Handler<PollPlayerDeathMessage> for GameServer {
-    type Result = usize;
-
-    fn handle(&mut self, gameinfo: PollPlayerDeathMessage, ctx: &mut Context<Self>) -> Self::Result {
-        if let Some(current_game) = self.games.get_mut(&gameinfo.game_id) {
-            let addr_data = current_game.get_cloned_players_id_addr();
-
-            if !current_game.players[gameinfo.self_id].has_death_voted && gameinfo.pk == current_game.players[gameinfo.self_id].private_key && current_game.players[gameinfo.other_id].active {
-                current_game.players[gameinfo.self_id].has_death_voted = true;
-                current_game.players[gameinfo.other_id].been_death_voted_for += 1;
-
-                let active_player_count = Self::get_real_active_players(current_game).len();
-
-                if current_game.players[gameinfo.other_id].been_death_voted_for as f64 >= active_player_count as f64 * 0.5f64 {
-                    Self::broadcast_to_game(&PlayerDied{user_id: gameinfo.other_id}, None, addr_data);
-                    current_game.players[gameinfo.other_id].active = false;
-                }
-            }
-        } else {
-            println!("Death info message recived after game end id: {}", gameinfo.self_id);
-        }
-
-        gameinfo.self_id
-    }
-}
-
 impl Handler<GameTickerMessage> for GameServer {
     type Result = usize;
 
     fn handle(&mut self, gameinfo: GameTickerMessage, ctx: &mut Context<Self>) -> Self::Result {
         if let Some(current_game) = self.games.get_mut(&gameinfo.game_id) {
             if current_game.game_started {
+                if let Some(rejoin_deadline) = current_game.rejoin_deadline {
+                    let waiting = current_game.players.iter().any(|p| p.active && !p.is_ai && p.addr.is_none());
+                    if waiting && Instant::now() < rejoin_deadline {
+                        return gameinfo.game_id;
+                    }
+
+                    // Humans who didn't come back are out
+                    current_game.rejoin_deadline = None;
+                    let absent: Vec<_> = current_game.players.iter()
+                        .filter(|p| p.active && !p.is_ai && p.addr.is_none())
+                        .map(|p| p.id)
+                        .collect();
+                    let addr_data = current_game.get_cloned_players_id_addr();
+                    for player_id in absent {
+                        current_game.eliminate_player(player_id, None);
+                        Self::broadcast_to_game(&PlayerDied { user_id: player_id }, None, addr_data.clone());
+                    }
+                }
+
                 let addr_data = current_game.get_cloned_players_id_addr();
 
-                let mut all_players: Vec<_> = current_game.players.iter_mut().collect();
                 { // Mark discconnected players as inactive
-                    let marked_active_players = all_players.iter_mut().filter(|p| p.active);
-                    for player in marked_active_players {
-                        if let Some(ref addr) = player.addr {
-                            if !addr.connected() {
-                                player.active = false;
-                                Self::broadcast_to_game(
-                                    &PlayerDied{user_id: player.id},
-                                    None,
-                                    addr_data.clone()
-                                );
-                            }
-                        }
+                    let disconnected_players: Vec<_> = current_game.players.iter()
+                        .filter(|p| p.active)
+                        .filter(|p| if let Some(ref addr) = p.addr { !addr.connected() } else { false })
+                        .map(|p| p.id)
+                        .collect();
+                    for player_id in disconnected_players {
+                        current_game.eliminate_player(player_id, None);
+                        Self::broadcast_to_game(
+                            &PlayerDied{user_id: player_id},
+                            None,
+                            addr_data.clone()
+                        );
                     }
                 }
 
+                let remaining_sides = current_game.remaining_sides();
+                let all_players: Vec<_> = current_game.players.iter_mut().collect();
+
                 let connected_players_havent_sent_animation = all_players
                     .iter().filter(|p| {
                         if let Some(ref addr) = p.addr {
@@ -688,13 +1492,32 @@ impl Handler<GameTickerMessage> for GameServer {
                         });
                     }
 
-                    let alive_players = all_players
-                        .iter()
-                        .filter(|p| p.active)
-                        .collect::<Vec<_>>().len();
+                    // End the game once one player (or team) is left, or none when the last ones die together
+                    if remaining_sides <= 1 {
+                        let game_over = GameOver::from(gameinfo.game_id, current_game);
+                        Self::broadcast_to_game(&game_over, None, addr_data);
+                        let account_ids: Vec<_> = current_game.players.iter().map(|p| p.account_id).collect();
+                        if let Err(err) = self.storage.lock().unwrap().record_match(&game_over, &account_ids) {
+                            println!("Failed to record game {}: {}", gameinfo.game_id, err);
+                        }
+                        // Bots ignore their options so only humans say anything about the cards
+                        let card_usage = CardUsage::from_game(&game_over, false);
+                        if let Err(err) = self.storage.lock().unwrap().record_card_usage(&card_usage) {
+                            println!("Failed to record card usage of game {}: {}", gameinfo.game_id, err);
+                        }
+                        if current_game.queue == QueueType::Ranked {
+                            if let Err(err) = Self::update_ratings(&self.storage, current_game, &game_over) {
+                                println!("Failed to update ratings for game {}: {}", gameinfo.game_id, err);
+                            }
+                        }
+                        if let Err(err) = replay::save(gameinfo.game_id, &current_game.replay_log) {
+                            println!("Failed to save replay of game {}: {}", gameinfo.game_id, err);
+                        }
+                        self.recent_results.push_back(game_over);
+                        if self.recent_results.len() > MAX_RECENT_RESULTS {
+                            self.recent_results.pop_front();
+                        }
 
-                    // If there is only one alive player end game
-                    if alive_players == 1 {
                         ctx.address().do_send(DeleteGame {
                             game_id: gameinfo.game_id,
                             game_tick_handle: current_game.game_tick_handle,
@@ -769,11 +1592,120 @@ impl Handler<DeleteGame> for GameServer {
         println!("{} has concluded", gameinfo.game_id);
         ctx.cancel_future(gameinfo.game_tick_handle);
         self.games.remove(&gameinfo.game_id);
+        match snapshot::remove(&self.snapshot_dir, gameinfo.game_id) {
+            Ok(()) => { self.snapshotted.remove(&gameinfo.game_id); },
+            Err(err) => println!("Failed to remove snapshot of game {}: {}", gameinfo.game_id, err),
+        }
 
         gameinfo.game_id
     }
 }
 
+impl Handler<RejoinMessage> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: RejoinMessage, _: &mut Context<Self>) -> Self::Result {
+        let current_game = match self.games.get_mut(&msg.game_id) {
+            Some(current_game) => current_game,
+            None => {
+                let _ = msg.addr.do_send(ToUserMessage(String::from("Game not found")));
+                return 0;
+            },
+        };
+        let current_player = match current_game.players.get_mut(msg.player_id) {
+            Some(player) if player.private_key == msg.pk && !player.is_ai && player.active => player,
+            _ => {
+                let _ = msg.addr.do_send(ToUserMessage(String::from("Can't rejoin game")));
+                return 0;
+            },
+        };
+        current_player.addr = Some(msg.addr.clone());
+        println!("{} rejoined game {}", current_player.username, msg.game_id);
+
+        let current_player = &current_game.players[msg.player_id];
+        if let Ok(json_string) = serde_json::to_string(current_player) {
+            let _ = msg.addr.do_send(ToUserMessage(json_string));
+        }
+
+        let rules = GameRules {
+            mode: current_game.mode,
+            queue: current_game.queue,
+            team_count: if current_game.mode == GameMode::Teams { Some(self.gc.team_count) } else { None },
+            friendly_fire: self.gc.friendly_fire,
+            palette: self.gc.cosmetics.palette.clone(),
+        };
+        if let Ok(json_string) = serde_json::to_string(&rules) {
+            let _ = msg.addr.do_send(ToUserMessage(json_string));
+        }
+
+        let map_info = MapInfo { map: current_game.map.clone() };
+        if let Ok(json_string) = serde_json::to_string(&map_info) {
+            let _ = msg.addr.do_send(ToUserMessage(json_string));
+        }
+
+        for some_player in current_game.seated_players().map(PlayerJoin::from) {
+            if let Ok(json_string) = serde_json::to_string(&some_player) {
+                let _ = msg.addr.do_send(ToUserMessage(json_string));
+            }
+        }
+        if let Ok(json_string) = serde_json::to_string(&StatusEffects::from(current_game)) {
+            let _ = msg.addr.do_send(ToUserMessage(json_string));
+        }
+
+        // The options of the turn in progress, unless the player already programmed it
+        if let Some(ref card_options) = current_player.card_options {
+            let card_options = CardOptions {
+                card_options: card_options.clone(),
+                slots: self.gc.program_slots,
+                hand: current_player.deck.as_ref().map(|deck| deck.hand.clone()),
+                player_id: current_player.id,
+                turn_id: current_game.turn_index,
+            };
+            if let Ok(json_string) = serde_json::to_string(&card_options) {
+                let _ = msg.addr.do_send(ToUserMessage(json_string));
+            }
+        }
+
+        msg.player_id
+    }
+}
+
+impl Handler<Drain> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Drain, ctx: &mut Context<Self>) -> Self::Result {
+        if self.drain_deadline.is_some() {
+            return self.games.len();
+        }
+
+        let secs = msg.secs.min(MAX_DRAIN_SECS);
+        self.drain_deadline = Some(Instant::now() + Duration::from_secs(secs));
+        let lobbies: Vec<_> = self.games.iter().filter(|(_, game)| !game.game_started).map(|(game_id, _)| *game_id).collect();
+        for game_id in lobbies {
+            if let Some(game) = self.games.remove(&game_id) {
+                ctx.cancel_future(game.game_tick_handle);
+                Self::broadcast_to_game(&ServerShuttingDown { secs: 0 }, None, game.get_cloned_players_id_addr());
+            }
+        }
+        for game in self.games.values() {
+            Self::broadcast_to_game(&ServerShuttingDown { secs: secs }, None, game.get_cloned_players_id_addr());
+        }
+        println!("Draining, {} games have {} seconds to finish", self.games.len(), secs);
+
+        ctx.run_interval(Duration::from_secs(1), |act, ctx| act.check_drain(ctx));
+
+        self.games.len()
+    }
+}
+
+impl Handler<DrainStatus> for GameServer {
+    type Result = bool;
+
+    fn handle(&mut self, _: DrainStatus, _: &mut Context<Self>) -> Self::Result {
+        self.drained
+    }
+}
+
 impl Handler<MessagePrune> for GameServer {
     type Result = usize;
 
@@ -811,6 +1743,321 @@ impl Handler<MessageFullClean> for GameServer {
     }
 }
 
+impl Handler<ChatMessage> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: ChatMessage, _: &mut Context<Self>) -> Self::Result {
+        if let Some(current_game) = self.games.get_mut(&msg.game_id) {
+            let turn_index = current_game.turn_index;
+            let player = match current_game.players.get_mut(msg.player_id) {
+                Some(player) if player.private_key == msg.pk => player,
+                _ => return 0,
+            };
+
+            let rejection = if player.chat_muted {
+                Some("You are muted")
+            } else if msg.text.trim().is_empty() {
+                Some("Chat message is empty")
+            } else if msg.text.chars().count() > MAX_CHAT_LEN {
+                Some("Chat message too long")
+            } else {
+                let now = Instant::now();
+                while player.recent_chats.front().map(|sent| now.duration_since(*sent) > CHAT_RATE_WINDOW).unwrap_or(false) {
+                    player.recent_chats.pop_front();
+                }
+
+                if player.recent_chats.len() >= CHAT_RATE_LIMIT {
+                    Some("Sending chat messages too fast")
+                } else {
+                    player.recent_chats.push_back(now);
+                    None
+                }
+            };
+
+            if let Some(rejection) = rejection {
+                if let Some(ref addr) = player.addr {
+                    let _ = addr.do_send(ToUserMessage(String::from(rejection)));
+                }
+                return 0;
+            }
+
+            let chat = PlayerChat {
+                user_id: player.id,
+                username: player.username.clone(),
+                text: String::from(msg.text.trim()),
+            };
+            current_game.replay_log.push(ReplayEvent::Chat {
+                turn: turn_index,
+                user_id: chat.user_id,
+                text: chat.text.clone(),
+            });
+
+            // Skip players that muted the sender
+            let addr_data = current_game.get_cloned_players_id_addr()
+                .into_iter()
+                .filter(|(i, _)| !current_game.players[*i].muted_players.contains(&msg.player_id))
+                .collect();
+            Self::broadcast_to_game(&chat, None, addr_data);
+        }
+
+        msg.player_id
+    }
+}
+
+impl Handler<ReadyMessage> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: ReadyMessage, _: &mut Context<Self>) -> Self::Result {
+        if let Some(current_game) = self.games.get_mut(&msg.game_id) {
+            if current_game.game_started {
+                return 0;
+            }
+
+            match current_game.players.get_mut(msg.player_id) {
+                Some(player) if player.private_key == msg.pk => player.ready = msg.ready,
+                _ => return 0,
+            }
+
+            // Once every human is ready there is no need to sit through the whole countdown,
+            // someone un-readying puts the original deadline back
+            let humans = current_game.connected_humans();
+            if humans.len() >= current_game.settings.min_humans && humans.iter().all(|p| p.ready) {
+                let ready_deadline = Instant::now() + Duration::from_secs(current_game.settings.ready_countdown_secs);
+                if ready_deadline < current_game.countdown_deadline {
+                    current_game.unready_deadline.get_or_insert(current_game.countdown_deadline);
+                    current_game.countdown_deadline = ready_deadline;
+                }
+            } else if let Some(unready_deadline) = current_game.unready_deadline.take() {
+                current_game.countdown_deadline = unready_deadline;
+            }
+
+            Self::broadcast_to_game(&LobbyState::from(current_game), None, current_game.get_cloned_players_id_addr());
+        }
+
+        msg.player_id
+    }
+}
+
+impl Handler<HostCommandMessage> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: HostCommandMessage, _: &mut Context<Self>) -> Self::Result {
+        let is_host = match self.games.get(&msg.game_id) {
+            Some(current_game) => match current_game.players.get(msg.player_id) {
+                Some(player) => player.private_key == msg.pk && current_game.host_id == Some(msg.player_id) && !current_game.game_started,
+                None => false,
+            },
+            None => false,
+        };
+        if !is_host {
+            return 0;
+        }
+
+        // Maps come out of the server wide rotation so they are picked before borrowing the game
+        let map_candidates = match msg.command {
+            HostCommand::SetMode { mode } => Some(self.next_maps(mode)),
+            HostCommand::SetMap { ref map } => {
+                match self.maps.iter().find(|m| &m.name == map) {
+                    Some(map) => Some(vec![map.clone()]),
+                    None if map == "Open" => Some(vec![MapDefinition::open(BOARD_SIZE)]),
+                    None => return 0,
+                }
+            },
+            _ => None,
+        };
+
+        let current_game = self.games.get_mut(&msg.game_id).unwrap();
+        match msg.command {
+            HostCommand::SetMode { mode } => {
+                current_game.mode = mode;
+                for player_id in 0 .. current_game.players.len() {
+                    current_game.players[player_id].team = None;
+                    if mode == GameMode::Teams && !current_game.players[player_id].kicked {
+                        let team = Self::pick_team(current_game, None, self.gc.team_count);
+                        current_game.players[player_id].team = Some(team);
+                    }
+                }
+
+                Self::broadcast_to_game(&GameRules {
+                    mode: mode,
+                    queue: current_game.queue,
+                    team_count: if mode == GameMode::Teams { Some(self.gc.team_count) } else { None },
+                    friendly_fire: self.gc.friendly_fire,
+                    palette: self.gc.cosmetics.palette.clone(),
+                }, None, current_game.get_cloned_players_id_addr());
+            },
+            HostCommand::SetMap { .. } => (),
+            HostCommand::SetBotDifficulty { difficulty } => current_game.settings.bot_difficulty = difficulty,
+            HostCommand::SetTimers { countdown_secs, ready_countdown_secs } => {
+                let countdown_secs = countdown_secs.clamp(MIN_COUNTDOWN_SECS, MAX_COUNTDOWN_SECS);
+                current_game.settings.countdown_secs = countdown_secs;
+                current_game.settings.ready_countdown_secs = ready_countdown_secs.min(countdown_secs);
+                current_game.countdown_deadline = Instant::now() + Duration::from_secs(countdown_secs);
+                current_game.unready_deadline = None;
+            },
+            HostCommand::SetDeck { ref deck } => {
+                current_game.settings.deck = deck.as_ref().map(|deck| DeckSettings {
+                    hand_size: deck.hand_size.min(MAX_HAND_SIZE),
+                    max_copies: deck.max_copies.clamp(1, MAX_DECK_COPIES),
+                });
+            },
+            HostCommand::Kick { target_id } => {
+                if target_id == msg.player_id || current_game.players.get(target_id).map(|p| p.kicked).unwrap_or(true) {
+                    return 0;
+                }
+
+                let addr = current_game.kick_player(target_id, &mut self.rng);
+                println!("{} was kicked from game {}", current_game.players[target_id].username, msg.game_id);
+                if let Some(addr) = addr {
+                    let _ = addr.do_send(ToUserMessage(String::from("{\"type\": \"Kicked\"}")));
+                }
+            },
+            HostCommand::StartNow => {
+                current_game.countdown_deadline = Instant::now();
+                current_game.unready_deadline = None;
+            },
+        }
+
+        if let Some(map_candidates) = map_candidates {
+            current_game.map_candidates = map_candidates;
+            current_game.map_votes.clear();
+            Self::apply_map_vote(current_game, &mut self.rng);
+            if current_game.map_candidates.len() > 1 {
+                Self::broadcast_to_game(&MapVoteOptions {
+                    maps: current_game.map_candidates.iter().map(|map| map.name.clone()).collect(),
+                    votes: current_game.map_vote_counts(),
+                }, None, current_game.get_cloned_players_id_addr());
+            }
+        }
+
+        Self::broadcast_to_game(&LobbyState::from(current_game), None, current_game.get_cloned_players_id_addr());
+
+        msg.player_id
+    }
+}
+
+impl Handler<MapVoteMessage> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: MapVoteMessage, _: &mut Context<Self>) -> Self::Result {
+        if let Some(current_game) = self.games.get_mut(&msg.game_id) {
+            let can_vote = match current_game.players.get(msg.player_id) {
+                Some(player) => player.private_key == msg.pk && !player.is_ai,
+                None => false,
+            };
+
+            if can_vote && !current_game.game_started && msg.map_index < current_game.map_candidates.len() {
+                current_game.map_votes.insert(msg.player_id, msg.map_index);
+                Self::broadcast_to_game(&MapVoteOptions {
+                    maps: current_game.map_candidates.iter().map(|map| map.name.clone()).collect(),
+                    votes: current_game.map_vote_counts(),
+                }, None, current_game.get_cloned_players_id_addr());
+            }
+        }
+
+        msg.player_id
+    }
+}
+
+impl Handler<EmoteMessage> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: EmoteMessage, _: &mut Context<Self>) -> Self::Result {
+        if let Some(current_game) = self.games.get_mut(&msg.game_id) {
+            let addr_data = current_game.get_cloned_players_id_addr();
+            let tile_on_board = msg.tile.map(|tile| current_game.is_on_board(tile)).unwrap_or(true);
+            let player = match current_game.players.get_mut(msg.player_id) {
+                Some(player) if player.private_key == msg.pk => player,
+                _ => return 0,
+            };
+
+            let emote = match self.gc.emotes.get(msg.emote_id as usize) {
+                Some(emote) => emote.clone(),
+                None => return 0,
+            };
+            if !tile_on_board || (!player.active && !self.gc.dead_can_emote) {
+                return 0;
+            }
+
+            // Emotes sent during the cooldown are dropped
+            let now = Instant::now();
+            if player.last_emote.map(|sent| now.duration_since(sent) < EMOTE_COOLDOWN).unwrap_or(false) {
+                return 0;
+            }
+            player.last_emote = Some(now);
+
+            Self::broadcast_to_game(&PlayerEmote {
+                user_id: player.id,
+                emote_id: msg.emote_id,
+                emote: emote,
+                tile: msg.tile,
+            }, None, addr_data);
+        }
+
+        msg.player_id
+    }
+}
+
+impl Handler<MutePlayerMessage> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: MutePlayerMessage, _: &mut Context<Self>) -> Self::Result {
+        if let Some(current_game) = self.games.get_mut(&msg.game_id) {
+            if let Some(player) = current_game.players.get_mut(msg.player_id) {
+                if player.private_key == msg.pk {
+                    if msg.muted {
+                        player.muted_players.insert(msg.target_id);
+                    } else {
+                        player.muted_players.remove(&msg.target_id);
+                    }
+                }
+            }
+        }
+
+        msg.player_id
+    }
+}
+
+impl Handler<MessageAdminMute> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: MessageAdminMute, _: &mut Context<Self>) -> Self::Result {
+        let player = self.games.get_mut(&msg.game_id).and_then(|game| game.players.get_mut(msg.player_id));
+
+        if let Some(player) = player {
+            player.chat_muted = msg.muted;
+            if let Some(ref addr) = player.addr {
+                let notice = if msg.muted { "You have been muted" } else { "You have been unmuted" };
+                let _ = addr.do_send(ToUserMessage(String::from(notice)));
+            }
+            let _ = msg.addr.do_send(ToUserMessage(format!("{} muted: {}", player.username, msg.muted)));
+        } else {
+            let _ = msg.addr.do_send(ToUserMessage(String::from("No such player")));
+        }
+
+        msg.player_id
+    }
+}
+
+impl Handler<MessageStats> for GameServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: MessageStats, _: &mut Context<Self>) -> Self::Result {
+        let mut results: Vec<GameOver> = self.games.iter()
+            .filter(|(game_id, _)| msg.game_id.is_none() || msg.game_id == Some(**game_id))
+            .map(|(game_id, game)| GameOver::from(*game_id, game))
+            .collect();
+        results.extend(self.recent_results.iter()
+            .filter(|result| msg.game_id.is_none() || msg.game_id == Some(result.game_id))
+            .cloned());
+
+        if let Ok(json_string) = serde_json::to_string(&results) {
+            let _ = msg.addr.do_send(ToUserMessage(json_string));
+        }
+        results.len()
+    }
+}
+
 impl Handler<Disconnect> for GameServer {
     type Result = i32;
 
@@ -1,3 +1,649 @@
+use rand::prelude::*;
+use serde::Serialize;
+
+use crate::config::{Action, Card, GameConfig, PassiveAbility};
+use crate::map::HazardKind;
+use crate::models::{Game, Mutation, Player, StatusKind};
+
+/// What happened while the cards of a turn resolved, in order, for clients to animate
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum SimEvent {
+    Moved { user_id: usize, to: (u16, u16) },
+    Rotated { user_id: usize, orientation: u8 },
+    Damaged { user_id: usize, attacker_id: usize, amount: u8, health: u8 },
+    Healed { user_id: usize, amount: u8, health: u8 },
+    Blocked { user_id: usize, attacker_id: usize },
+    StatusApplied { user_id: usize, kind: StatusKind, turns: u8 },
+    /// A stunned player's card was skipped
+    Skipped { user_id: usize, card_type: u8 },
+    Died { user_id: usize, killer_id: Option<usize> },
+    /// Damage from standing outside the ring at the end of a turn, shields don't block it
+    RingDamaged { user_id: usize, amount: u8, health: u8 },
+    /// Damage from ending a turn on a damage hazard, shields don't block it
+    HazardDamaged { user_id: usize, amount: u8, health: u8 },
+}
+
+/// The tile next to `tile` in the direction of `orientation`, `None` past the top or left edge
+pub fn neighbour(tile: (u16, u16), orientation: u8) -> Option<(u16, u16)> {
+    match orientation % 4 {
+        0 => tile.1.checked_sub(1).map(|y| (tile.0, y)),
+        1 => Some((tile.0 + 1, tile.1)),
+        2 => Some((tile.0, tile.1 + 1)),
+        _ => tile.0.checked_sub(1).map(|x| (x, tile.1)),
+    }
+}
+
+pub struct Simulator;
+
+impl Simulator {
+    /// Whether `attacker` is allowed to damage `target` under the game's friendly fire rule
+    pub fn can_damage(attacker: &Player, target: &Player, gc: &GameConfig) -> bool {
+        gc.friendly_fire || attacker.id == target.id || attacker.team.is_none() || attacker.team != target.team
+    }
+
+    /// Order the programmed cards play out in: every player's first slot, then every player's second, ...
+    /// Within a slot higher priority cards go first and ties go to the lower player id
+    pub fn resolution_order(players: &[Player], gc: &GameConfig) -> Vec<Mutation> {
+        let slots = players.iter().map(|p| p.program.len()).max().unwrap_or(0);
+        let priority = |mutation: &Mutation| gc.cards.get(mutation.card_type as usize).map(|card| card.priority).unwrap_or(0);
+        let mut order = Vec::new();
+
+        for slot in 0 .. slots {
+            let mut slot_order: Vec<_> = players.iter()
+                .filter(|p| p.active)
+                .filter_map(|p| p.program.get(slot).cloned().flatten())
+                .collect();
+            slot_order.sort_by_key(|mutation| (std::cmp::Reverse(priority(mutation)), mutation.user_id));
+            order.extend(slot_order);
+        }
+
+        order
+    }
+
+    /// Plays the cards in resolution order, then the hazards players ended the turn on.
+    /// Cards missing from the catalogue do nothing on the server
+    pub fn resolve_turn<R: Rng>(game: &mut Game, order: &[Mutation], gc: &GameConfig, rng: &mut R) -> Vec<SimEvent> {
+        let mut events = Vec::new();
+        for mutation in order {
+            if game.players[mutation.user_id].has_status(StatusKind::Stunned) {
+                events.push(SimEvent::Skipped { user_id: mutation.user_id, card_type: mutation.card_type });
+                continue;
+            }
+
+            if let Some(card) = gc.cards.get(mutation.card_type as usize) {
+                Self::play_card(game, mutation.user_id, card, gc, rng, &mut events);
+            }
+        }
+        Self::apply_hazards(game, &mut events);
+        Self::record_stats(game, &events);
+
+        events
+    }
+
+    /// Triggers the hazard under every active player once, a player moved onto another hazard
+    /// doesn't trigger it until the next turn. Conveyors and teleporters onto a blocked or
+    /// occupied tile leave the player where they are
+    fn apply_hazards(game: &mut Game, events: &mut Vec<SimEvent>) {
+        let on_hazards: Vec<_> = game.players.iter()
+            .filter(|p| p.active)
+            .filter_map(|p| game.map.hazard_at((p.pos.0, p.pos.1)).map(|hazard| (p.id, hazard.kind.clone())))
+            .collect();
+
+        for (user_id, kind) in on_hazards {
+            if !game.players[user_id].active {
+                continue;
+            }
+
+            let to = match kind {
+                HazardKind::Damage { amount } => {
+                    let player = &mut game.players[user_id];
+                    player.health = player.health.saturating_sub(amount);
+                    events.push(SimEvent::HazardDamaged { user_id: user_id, amount: amount, health: player.health });
+
+                    if player.health == 0 {
+                        game.eliminate_player(user_id, None);
+                        events.push(SimEvent::Died { user_id: user_id, killer_id: None });
+                    }
+                    continue;
+                },
+                HazardKind::Conveyor { direction } => {
+                    let pos = game.players[user_id].pos;
+                    neighbour((pos.0, pos.1), direction)
+                },
+                HazardKind::Teleporter { target } => Some(target),
+            };
+
+            if let Some(to) = to.filter(|to| Self::is_walkable(game, *to) && Self::player_at(game, *to).is_none()) {
+                let player = &mut game.players[user_id];
+                player.pos = (to.0, to.1, player.pos.2);
+                events.push(SimEvent::Moved { user_id: user_id, to: to });
+            }
+        }
+    }
+
+    /// Hurts every player left outside the ring, `ring_damage: None` kills them outright
+    pub fn apply_ring_damage(game: &mut Game, gc: &GameConfig) -> Vec<SimEvent> {
+        let mut events = Vec::new();
+        let outside: Vec<_> = game.players.iter()
+            .filter(|p| p.active && !game.bounds.contains((p.pos.0, p.pos.1)))
+            .map(|p| p.id)
+            .collect();
+
+        for user_id in outside {
+            let player = &mut game.players[user_id];
+            let amount = gc.ring_damage.unwrap_or(player.health);
+            player.health = player.health.saturating_sub(amount);
+            events.push(SimEvent::RingDamaged { user_id: user_id, amount: amount, health: player.health });
+
+            if player.health == 0 {
+                game.eliminate_player(user_id, None);
+                events.push(SimEvent::Died { user_id: user_id, killer_id: None });
+            }
+        }
+        Self::record_stats(game, &events);
+
+        events
+    }
+
+    /// Adds what happened to the players' match stats, every tile a player is moved counts towards their distance
+    fn record_stats(game: &mut Game, events: &[SimEvent]) {
+        for event in events {
+            match *event {
+                SimEvent::Moved { user_id, .. } => {
+                    let stats = &mut game.players[user_id].stats;
+                    stats.distance_moved = stats.distance_moved.saturating_add(1);
+                },
+                SimEvent::Damaged { user_id, attacker_id, amount, .. } => {
+                    let stats = &mut game.players[user_id].stats;
+                    stats.damage_taken = stats.damage_taken.saturating_add(amount as u32);
+                    if attacker_id != user_id {
+                        let stats = &mut game.players[attacker_id].stats;
+                        stats.damage_dealt = stats.damage_dealt.saturating_add(amount as u32);
+                    }
+                },
+                SimEvent::RingDamaged { user_id, amount, .. } | SimEvent::HazardDamaged { user_id, amount, .. } => {
+                    let stats = &mut game.players[user_id].stats;
+                    stats.damage_taken = stats.damage_taken.saturating_add(amount as u32);
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn play_card<R: Rng>(game: &mut Game, actor: usize, card: &Card, gc: &GameConfig, rng: &mut R, events: &mut Vec<SimEvent>) {
+        for action in card.actions.iter().filter_map(|action| gc.actions.get(*action)) {
+            // A player killed earlier in the turn doesn't get to finish their card
+            if !game.players[actor].active {
+                return;
+            }
+
+            let orientation = game.players[actor].pos.2;
+            let character = gc.character(game.players[actor].character_type);
+            match *action {
+                Action::Move { tiles } if tiles != 0 => {
+                    let distance = (i16::from(tiles).abs() + i16::from(character.move_modifier)).max(1);
+                    let direction = if game.players[actor].has_status(StatusKind::Disoriented) {
+                        rng.gen_range(0, 4)
+                    } else if tiles < 0 {
+                        (orientation + 2) % 4
+                    } else {
+                        orientation
+                    };
+                    for _ in 0 .. distance {
+                        if !Self::push_line(game, actor, actor, direction, gc, events) {
+                            break;
+                        }
+                    }
+                },
+                Action::Move { .. } => (),
+                Action::Rotate { quarter_turns } => {
+                    let player = &mut game.players[actor];
+                    player.pos.2 = (player.pos.2 % 4 + quarter_turns % 4) % 4;
+                    events.push(SimEvent::Rotated { user_id: actor, orientation: player.pos.2 });
+                },
+                Action::Push { tiles } => {
+                    if let Some(target) = Self::facing_player(game, actor, Some(1)) {
+                        for _ in 0 .. tiles {
+                            if !Self::push_line(game, target, actor, orientation, gc, events) {
+                                break;
+                            }
+                        }
+                    }
+                },
+                Action::Shoot { range } => {
+                    if let Some(target) = Self::facing_player(game, actor, range) {
+                        if Self::can_damage(&game.players[actor], &game.players[target], gc) {
+                            let bonus = if character.passive == Some(PassiveAbility::Sharpshooter) { 1 } else { 0 };
+                            Self::damage(game, actor, target, card.dmg.saturating_add(bonus), events);
+                            if card.disorient {
+                                Self::apply_status(game, target, StatusKind::Disoriented, gc.disorient_turns, gc, events);
+                            }
+                        }
+                    }
+                },
+                Action::Disorient { range, turns } => {
+                    if let Some(target) = Self::facing_player(game, actor, range) {
+                        Self::apply_status(game, target, StatusKind::Disoriented, turns, gc, events);
+                    }
+                },
+                Action::Stun { range, turns } => {
+                    if let Some(target) = Self::facing_player(game, actor, range) {
+                        Self::apply_status(game, target, StatusKind::Stunned, turns, gc, events);
+                    }
+                },
+                Action::Shield { turns } => Self::apply_status(game, actor, StatusKind::Shielded, turns, gc, events),
+                Action::Heal { amount } => {
+                    let player = &mut game.players[actor];
+                    player.health = player.health.saturating_add(amount).min(character.health);
+                    events.push(SimEvent::Healed { user_id: actor, amount: amount, health: player.health });
+                },
+            }
+        }
+    }
+
+    /// The active player standing on a tile
+    fn player_at(game: &Game, tile: (u16, u16)) -> Option<usize> {
+        game.players.iter().find(|p| p.active && (p.pos.0, p.pos.1) == tile).map(|p| p.id)
+    }
+
+    /// Whether a player could stand on the tile, ignoring other players. The edge of the ring
+    /// blocks like the edge of the board
+    fn is_walkable(game: &Game, tile: (u16, u16)) -> bool {
+        game.is_on_board(tile) && game.bounds.contains(tile) && !game.map.is_wall(tile)
+    }
+
+    /// Moves `first` one tile along with the line of players standing in front of it in `direction`.
+    ///
+    /// - A wall or the board edge in front of `first` blocks the move
+    /// - A line of players moves together if the tile past its end is free
+    /// - A line pushed into a wall or the edge stays put and everyone in it but `pusher` takes
+    ///   `collision_damage` from `pusher`
+    ///
+    /// Returns whether the line moved
+    fn push_line(game: &mut Game, first: usize, pusher: usize, direction: u8, gc: &GameConfig, events: &mut Vec<SimEvent>) -> bool {
+        let mut line = vec![first];
+        let mut tile = (game.players[first].pos.0, game.players[first].pos.1);
+        let end = loop {
+            match neighbour(tile, direction) {
+                Some(next) if Self::is_walkable(game, next) => match Self::player_at(game, next) {
+                    Some(next_player) => {
+                        line.push(next_player);
+                        tile = next;
+                    },
+                    None => break Some(next),
+                },
+                _ => break None,
+            }
+        };
+
+        if end.is_none() {
+            for pushed in line.into_iter().filter(|player_id| *player_id != pusher) {
+                let sturdy = gc.character(game.players[pushed].character_type).passive == Some(PassiveAbility::Sturdy);
+                if !sturdy && Self::can_damage(&game.players[pusher], &game.players[pushed], gc) {
+                    Self::damage(game, pusher, pushed, gc.collision_damage, events);
+                }
+            }
+            return false;
+        }
+
+        // Move the far end first so every player steps onto a tile that was just freed
+        for player_id in line.into_iter().rev() {
+            let player = &mut game.players[player_id];
+            let next = neighbour((player.pos.0, player.pos.1), direction).unwrap();
+            player.pos = (next.0, next.1, player.pos.2);
+            events.push(SimEvent::Moved { user_id: player_id, to: next });
+        }
+
+        true
+    }
+
+    /// The first player in the actor's facing direction within `range` tiles, walls block the view
+    fn facing_player(game: &Game, actor: usize, range: Option<u16>) -> Option<usize> {
+        let player = &game.players[actor];
+        let mut tile = (player.pos.0, player.pos.1);
+        let mut distance = 0;
+
+        while range.map(|range| distance < range).unwrap_or(true) {
+            tile = match neighbour(tile, player.pos.2) {
+                Some(next) if Self::is_walkable(game, next) => next,
+                _ => return None,
+            };
+            distance += 1;
+
+            if let Some(target) = Self::player_at(game, tile) {
+                return Some(target);
+            }
+        }
+
+        None
+    }
+
+    fn damage(game: &mut Game, attacker: usize, target: usize, amount: u8, events: &mut Vec<SimEvent>) {
+        let player = &mut game.players[target];
+        if player.has_status(StatusKind::Shielded) {
+            events.push(SimEvent::Blocked { user_id: target, attacker_id: attacker });
+            return;
+        }
+
+        player.health = player.health.saturating_sub(amount);
+        events.push(SimEvent::Damaged { user_id: target, attacker_id: attacker, amount: amount, health: player.health });
+
+        if player.health == 0 {
+            game.eliminate_player(target, Some(attacker));
+            events.push(SimEvent::Died { user_id: target, killer_id: Some(attacker) });
+        }
+    }
+
+    fn apply_status(game: &mut Game, target: usize, kind: StatusKind, turns: u8, gc: &GameConfig, events: &mut Vec<SimEvent>) {
+        let steady = gc.character(game.players[target].character_type).passive == Some(PassiveAbility::Steady);
+        if kind == StatusKind::Disoriented && steady {
+            return;
+        }
+
+        let turn_index = game.turn_index;
+        game.players[target].add_status(kind, turn_index, turns);
+        events.push(SimEvent::StatusApplied { user_id: target, kind: kind, turns: turns });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix::SpawnHandle;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::config::{CharacterDefinition, GameMode, QueueType};
+    use crate::map::{Hazard, MapDefinition};
+    use crate::models::NewPlayer;
+    use SimEvent::*;
+
+    const STEP: u8 = 0;
+    const QUICK_STEP: u8 = 1;
+    const PUSH: u8 = 2;
+    const DOUBLE_PUSH: u8 = 3;
+    const LONG_RETREAT: u8 = 4;
+    const FULL_SPIN: u8 = 5;
+
+    fn config(friendly_fire: bool) -> GameConfig {
+        let card = |priority: u8, action: usize| Card { disorient: false, dmg: 0, priority: priority, actions: vec![action] };
+        GameConfig {
+            actions: vec![
+                Action::Move { tiles: 1 },
+                Action::Push { tiles: 1 },
+                Action::Push { tiles: 2 },
+                Action::Move { tiles: -128 },
+                Action::Rotate { quarter_turns: 255 },
+            ],
+            cards: vec![card(0, 0), card(1, 0), card(0, 1), card(0, 2), card(0, 3), card(0, 4)],
+            characters: vec![CharacterDefinition {
+                name: String::from("Plain"),
+                health: 10,
+                move_modifier: 0,
+                passive: None,
+                card_weights: HashMap::new(),
+            }],
+            collision_damage: 1,
+            friendly_fire: friendly_fire,
+            ..GameConfig::default()
+        }
+    }
+
+    /// A turn on a board: `players` are `(x, y, orientation, team)` and `programs` their card per slot
+    struct Case {
+        name: &'static str,
+        board_size: (u16, u16),
+        walls: Vec<(u16, u16)>,
+        hazards: Vec<Hazard>,
+        players: Vec<(u16, u16, u8, Option<u8>)>,
+        programs: Vec<Vec<u8>>,
+        friendly_fire: bool,
+        positions: Vec<(u16, u16)>,
+        events: Vec<SimEvent>,
+        health: Vec<u8>,
+    }
+
+    impl Default for Case {
+        fn default() -> Self {
+            Case {
+                name: "",
+                board_size: (8, 3),
+                walls: Vec::new(),
+                hazards: Vec::new(),
+                players: Vec::new(),
+                programs: Vec::new(),
+                friendly_fire: false,
+                positions: Vec::new(),
+                events: Vec::new(),
+                health: Vec::new(),
+            }
+        }
+    }
+
+    fn run(case: &Case) -> (Game, Vec<SimEvent>) {
+        let gc = config(case.friendly_fire);
+        let mut rng = StdRng::seed_from_u64(0);
+        let map = MapDefinition {
+            walls: case.walls.clone(),
+            hazards: case.hazards.clone(),
+            ..MapDefinition::open(case.board_size)
+        };
+        let mut game = Game::new(GameMode::FreeForAll, QueueType::Casual, gc.lobby.clone(), vec![map], Vec::new(), SpawnHandle::default());
+        for (id, (x, y, orientation, team)) in case.players.iter().enumerate() {
+            let mut player = Player::new(NewPlayer { team: *team, ..NewPlayer::test(id, (*x, *y)) }, &mut rng);
+            player.pos.2 = *orientation;
+            player.program = case.programs[id].iter().enumerate().map(|(slot, card_type)| {
+                Some(Mutation { user_id: id, card_type: *card_type, card_location: slot as u8 })
+            }).collect();
+            game.players.push(player);
+        }
+
+        let order = Simulator::resolution_order(&game.players, &gc);
+        let events = Simulator::resolve_turn(&mut game, &order, &gc, &mut rng);
+        (game, events)
+    }
+
+    #[test]
+    fn turns_resolve_as_expected() {
+        let cases = vec![
+            Case {
+                name: "higher priority moves first",
+                players: vec![(1, 1, 1, None), (3, 1, 3, None)],
+                programs: vec![vec![STEP], vec![QUICK_STEP]],
+                positions: vec![(2, 1), (3, 1)],
+                events: vec![
+                    Moved { user_id: 1, to: (2, 1) },
+                    Moved { user_id: 1, to: (3, 1) },
+                    Moved { user_id: 0, to: (2, 1) },
+                ],
+                health: vec![10, 10],
+                ..Case::default()
+            },
+            Case {
+                name: "equal priority goes to the lower id",
+                players: vec![(1, 1, 1, None), (3, 1, 3, None)],
+                programs: vec![vec![STEP], vec![STEP]],
+                positions: vec![(1, 1), (2, 1)],
+                events: vec![
+                    Moved { user_id: 0, to: (2, 1) },
+                    Moved { user_id: 0, to: (1, 1) },
+                    Moved { user_id: 1, to: (2, 1) },
+                ],
+                health: vec![10, 10],
+                ..Case::default()
+            },
+            Case {
+                name: "slots resolve in order",
+                players: vec![(1, 1, 1, None), (5, 1, 3, None)],
+                programs: vec![vec![QUICK_STEP, STEP], vec![STEP, QUICK_STEP]],
+                positions: vec![(3, 1), (4, 1)],
+                events: vec![
+                    Moved { user_id: 0, to: (2, 1) },
+                    Moved { user_id: 1, to: (4, 1) },
+                    Moved { user_id: 1, to: (3, 1) },
+                    Moved { user_id: 1, to: (4, 1) },
+                    Moved { user_id: 0, to: (3, 1) },
+                ],
+                health: vec![10, 10],
+                ..Case::default()
+            },
+            Case {
+                name: "a chain of players is pushed together",
+                players: vec![(1, 1, 1, None), (2, 1, 0, None), (3, 1, 0, None)],
+                programs: vec![vec![PUSH], vec![], vec![]],
+                positions: vec![(1, 1), (3, 1), (4, 1)],
+                events: vec![
+                    Moved { user_id: 2, to: (4, 1) },
+                    Moved { user_id: 1, to: (3, 1) },
+                ],
+                health: vec![10, 10, 10],
+                ..Case::default()
+            },
+            Case {
+                name: "a chain pushed into the edge stays put and is hurt",
+                board_size: (5, 3),
+                players: vec![(2, 1, 1, None), (3, 1, 0, None), (4, 1, 0, None)],
+                programs: vec![vec![PUSH], vec![], vec![]],
+                positions: vec![(2, 1), (3, 1), (4, 1)],
+                events: vec![
+                    Damaged { user_id: 1, attacker_id: 0, amount: 1, health: 9 },
+                    Damaged { user_id: 2, attacker_id: 0, amount: 1, health: 9 },
+                ],
+                health: vec![10, 9, 9],
+                ..Case::default()
+            },
+            Case {
+                name: "a chain pushed into a wall stays put and is hurt",
+                walls: vec![(4, 1)],
+                players: vec![(1, 1, 1, None), (2, 1, 0, None), (3, 1, 0, None)],
+                programs: vec![vec![PUSH], vec![], vec![]],
+                positions: vec![(1, 1), (2, 1), (3, 1)],
+                events: vec![
+                    Damaged { user_id: 1, attacker_id: 0, amount: 1, health: 9 },
+                    Damaged { user_id: 2, attacker_id: 0, amount: 1, health: 9 },
+                ],
+                health: vec![10, 9, 9],
+                ..Case::default()
+            },
+            Case {
+                name: "a chain pushed into another chain joins it",
+                walls: vec![(7, 1)],
+                players: vec![(1, 1, 1, None), (2, 1, 0, None), (3, 1, 0, None), (5, 1, 0, None), (6, 1, 0, None)],
+                programs: vec![vec![DOUBLE_PUSH], vec![], vec![], vec![], vec![]],
+                positions: vec![(1, 1), (3, 1), (4, 1), (5, 1), (6, 1)],
+                events: vec![
+                    Moved { user_id: 2, to: (4, 1) },
+                    Moved { user_id: 1, to: (3, 1) },
+                    Damaged { user_id: 1, attacker_id: 0, amount: 1, health: 9 },
+                    Damaged { user_id: 2, attacker_id: 0, amount: 1, health: 9 },
+                    Damaged { user_id: 3, attacker_id: 0, amount: 1, health: 9 },
+                    Damaged { user_id: 4, attacker_id: 0, amount: 1, health: 9 },
+                ],
+                health: vec![10, 9, 9, 9, 9],
+                ..Case::default()
+            },
+            Case {
+                name: "a walk into the edge hurts the players in front but not the walker",
+                board_size: (4, 3),
+                players: vec![(1, 1, 1, None), (2, 1, 0, None), (3, 1, 0, None)],
+                programs: vec![vec![STEP], vec![], vec![]],
+                positions: vec![(1, 1), (2, 1), (3, 1)],
+                events: vec![
+                    Damaged { user_id: 1, attacker_id: 0, amount: 1, health: 9 },
+                    Damaged { user_id: 2, attacker_id: 0, amount: 1, health: 9 },
+                ],
+                health: vec![10, 9, 9],
+                ..Case::default()
+            },
+            Case {
+                name: "collisions spare team mates without friendly fire",
+                board_size: (5, 3),
+                players: vec![(2, 1, 1, Some(0)), (3, 1, 0, Some(0)), (4, 1, 0, Some(1))],
+                programs: vec![vec![PUSH], vec![], vec![]],
+                positions: vec![(2, 1), (3, 1), (4, 1)],
+                events: vec![
+                    Damaged { user_id: 2, attacker_id: 0, amount: 1, health: 9 },
+                ],
+                health: vec![10, 10, 9],
+                ..Case::default()
+            },
+            Case {
+                name: "collisions hurt team mates with friendly fire",
+                board_size: (5, 3),
+                players: vec![(2, 1, 1, Some(0)), (3, 1, 0, Some(0)), (4, 1, 0, Some(1))],
+                programs: vec![vec![PUSH], vec![], vec![]],
+                friendly_fire: true,
+                positions: vec![(2, 1), (3, 1), (4, 1)],
+                events: vec![
+                    Damaged { user_id: 1, attacker_id: 0, amount: 1, health: 9 },
+                    Damaged { user_id: 2, attacker_id: 0, amount: 1, health: 9 },
+                ],
+                health: vec![10, 9, 9],
+                ..Case::default()
+            },
+            Case {
+                name: "extreme moves and turns don't overflow",
+                board_size: (4, 3),
+                players: vec![(2, 1, 1, None)],
+                programs: vec![vec![LONG_RETREAT, FULL_SPIN]],
+                positions: vec![(0, 1)],
+                events: vec![
+                    Moved { user_id: 0, to: (1, 1) },
+                    Moved { user_id: 0, to: (0, 1) },
+                    Rotated { user_id: 0, orientation: 0 },
+                ],
+                health: vec![10],
+                ..Case::default()
+            },
+            Case {
+                name: "hazards trigger once at the end of the turn",
+                hazards: vec![
+                    Hazard { tile: (2, 1), kind: HazardKind::Conveyor { direction: 1 } },
+                    Hazard { tile: (3, 1), kind: HazardKind::Damage { amount: 3 } },
+                    Hazard { tile: (5, 1), kind: HazardKind::Teleporter { target: (5, 2) } },
+                ],
+                players: vec![(1, 1, 1, None), (4, 1, 1, None)],
+                programs: vec![vec![STEP], vec![STEP]],
+                positions: vec![(3, 1), (5, 2)],
+                events: vec![
+                    Moved { user_id: 0, to: (2, 1) },
+                    Moved { user_id: 1, to: (5, 1) },
+                    Moved { user_id: 0, to: (3, 1) },
+                    Moved { user_id: 1, to: (5, 2) },
+                ],
+                health: vec![10, 10],
+                ..Case::default()
+            },
+        ];
+
+        for case in cases.iter() {
+            let (game, events) = run(case);
+            let positions: Vec<_> = game.players.iter().map(|p| (p.pos.0, p.pos.1)).collect();
+            let health: Vec<_> = game.players.iter().map(|p| p.health).collect();
+            assert_eq!(positions, case.positions, "positions: {}", case.name);
+            assert_eq!(events, case.events, "events: {}", case.name);
+            assert_eq!(health, case.health, "health: {}", case.name);
+        }
+    }
+
+    #[test]
+    fn turns_add_to_the_match_stats() {
+        let (game, _) = run(&Case {
+            walls: vec![(4, 1)],
+            players: vec![(1, 1, 1, None), (2, 1, 0, None), (3, 1, 0, None), (0, 2, 1, None)],
+            programs: vec![vec![PUSH], vec![], vec![], vec![QUICK_STEP]],
+            ..Case::default()
+        });
+
+        let stats: Vec<_> = game.players.iter()
+            .map(|p| (p.stats.damage_dealt, p.stats.damage_taken, p.stats.distance_moved))
+            .collect();
+        assert_eq!(stats, vec![(2, 0, 0), (0, 1, 0), (0, 1, 0), (0, 0, 1)]);
+    }
+}
+
 // use std::collections::{HashMap, HashSet};
 
 // use crate::config::*;
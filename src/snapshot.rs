@@ -0,0 +1,316 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use actix::SpawnHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{GameMode, LobbySettings, QueueType};
+use crate::map::MapDefinition;
+use crate::models::{Bounds, Cosmetics, Deck, Game, Mutation, Player, PlayerStats, StatusEffect};
+use crate::replay::ReplayEvent;
+
+/// Everything needed to carry on with a player, their connection and chat rate limits start over
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub id: usize,
+    pub username: String,
+    pub private_key: String,
+    pub character_type: u8,
+    pub team: Option<u8>,
+    pub pos: (u16, u16, u8),
+    pub is_ai: bool,
+    pub active: bool,
+    #[serde(default)]
+    pub kicked: bool,
+    pub health: u8,
+    pub cosmetics: Cosmetics,
+    pub account_id: Option<i64>,
+    pub rating: f64,
+    pub previous_choices: Vec<Mutation>,
+    pub card_options: Option<Vec<u8>>,
+    pub program: Vec<Option<Mutation>>,
+    pub animation_done: bool,
+    pub game_id: usize,
+    pub stats: PlayerStats,
+    pub muted_players: HashSet<usize>,
+    pub chat_muted: bool,
+    pub ready: bool,
+    pub deck: Option<Deck>,
+    pub status_effects: Vec<StatusEffect>,
+}
+
+impl PlayerSnapshot {
+    pub fn from(player: &Player) -> Self {
+        PlayerSnapshot {
+            id: player.id,
+            username: player.username.clone(),
+            private_key: player.private_key.clone(),
+            character_type: player.character_type,
+            team: player.team,
+            pos: player.pos,
+            is_ai: player.is_ai,
+            active: player.active,
+            kicked: player.kicked,
+            health: player.health,
+            cosmetics: player.cosmetics,
+            account_id: player.account_id,
+            rating: player.rating,
+            previous_choices: player.previous_choices.clone(),
+            card_options: player.card_options.clone(),
+            program: player.program.clone(),
+            animation_done: player.animation_done,
+            game_id: player.game_id,
+            stats: player.stats.clone(),
+            muted_players: player.muted_players.clone(),
+            chat_muted: player.chat_muted,
+            ready: player.ready,
+            deck: player.deck.clone(),
+            status_effects: player.status_effects.clone(),
+        }
+    }
+
+    /// The player without a connection, humans get one back when they rejoin
+    pub fn restore(self) -> Player {
+        Player {
+            id: self.id,
+            username: self.username,
+            private_key: self.private_key,
+            character_type: self.character_type,
+            team: self.team,
+            pos: self.pos,
+            is_ai: self.is_ai,
+            active: self.active,
+            kicked: self.kicked,
+            health: self.health,
+            cosmetics: self.cosmetics,
+            account_id: self.account_id,
+            rating: self.rating,
+            previous_choices: self.previous_choices,
+            card_options: self.card_options,
+            program: self.program,
+            animation_done: self.animation_done,
+            addr: None,
+            game_id: self.game_id,
+            stats: self.stats,
+            recent_chats: Default::default(),
+            muted_players: self.muted_players,
+            chat_muted: self.chat_muted,
+            last_emote: None,
+            ready: self.ready,
+            deck: self.deck,
+            status_effects: self.status_effects,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub game_id: usize,
+    pub mode: GameMode,
+    pub queue: QueueType,
+    pub settings: LobbySettings,
+    pub host_id: Option<usize>,
+    pub map: MapDefinition,
+    pub map_candidates: Vec<MapDefinition>,
+    pub map_votes: HashMap<usize, usize>,
+    pub board_size: (u16, u16),
+    pub bounds: Bounds,
+    pub game_started: bool,
+    pub players: Vec<PlayerSnapshot>,
+    pub turn_index: usize,
+    #[serde(default)]
+    pub turn_resolved: bool,
+    pub available_pos: Vec<(u16, u16)>,
+    pub elimination_order: Vec<usize>,
+    pub replay_log: Vec<ReplayEvent>,
+}
+
+impl GameSnapshot {
+    pub fn from(game_id: usize, game: &Game) -> Self {
+        GameSnapshot {
+            game_id: game_id,
+            mode: game.mode,
+            queue: game.queue,
+            settings: game.settings.clone(),
+            host_id: game.host_id,
+            map: game.map.clone(),
+            map_candidates: game.map_candidates.clone(),
+            map_votes: game.map_votes.clone(),
+            board_size: game.board_size,
+            bounds: game.bounds,
+            game_started: game.game_started,
+            players: game.players.iter().map(PlayerSnapshot::from).collect(),
+            turn_index: game.turn_index,
+            turn_resolved: game.turn_resolved,
+            available_pos: game.available_pos.clone(),
+            elimination_order: game.elimination_order.clone(),
+            replay_log: game.replay_log.clone(),
+        }
+    }
+
+    /// The game with none of its timers running, the server starts them again
+    pub fn restore(self) -> Game {
+        Game {
+            mode: self.mode,
+            queue: self.queue,
+            settings: self.settings,
+            host_id: self.host_id,
+            countdown_deadline: Instant::now(),
+            unready_deadline: None,
+            map: self.map,
+            map_candidates: self.map_candidates,
+            map_votes: self.map_votes,
+            board_size: self.board_size,
+            bounds: self.bounds,
+            game_started: self.game_started,
+            players: self.players.into_iter().map(|p| p.restore()).collect(),
+            turn_index: self.turn_index,
+            turn_resolved: self.turn_resolved,
+            available_pos: self.available_pos,
+            game_countdown_handle: None,
+            has_loop_countdown: false,
+            game_tick_handle: SpawnHandle::default(),
+            elimination_order: self.elimination_order,
+            replay_log: self.replay_log,
+            rejoin_deadline: None,
+        }
+    }
+}
+
+/// Where snapshots are kept, `LD47_SNAPSHOT_DIR` or `snapshots`
+pub fn snapshot_dir() -> PathBuf {
+    PathBuf::from(env::var("LD47_SNAPSHOT_DIR").unwrap_or_else(|_| String::from("snapshots")))
+}
+
+/// Writes a game to `<dir>/<game_id>.json`, through a temporary file so a crash while writing
+/// leaves the previous snapshot intact. Returns a hash of the snapshot, the write is skipped
+/// when it matches `previous`
+pub fn save(dir: &Path, game_id: usize, game: &Game, previous: Option<u64>) -> io::Result<u64> {
+    let json_string = serde_json::to_string(&GameSnapshot::from(game_id, game))?;
+    let mut hasher = DefaultHasher::new();
+    json_string.hash(&mut hasher);
+    let hash = hasher.finish();
+    if previous == Some(hash) {
+        return Ok(hash);
+    }
+
+    fs::create_dir_all(dir)?;
+    let tmp_path = dir.join(format!("{}.json.tmp", game_id));
+    fs::write(&tmp_path, json_string)?;
+    fs::rename(tmp_path, dir.join(format!("{}.json", game_id)))?;
+
+    Ok(hash)
+}
+
+pub fn remove(dir: &Path, game_id: usize) -> io::Result<()> {
+    match fs::remove_file(dir.join(format!("{}.json", game_id))) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Every snapshot on disk, ones that fail to load are skipped
+pub fn load_all(dir: &Path) -> Vec<GameSnapshot> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut snapshots = Vec::new();
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.extension().map(|ext| ext != "json").unwrap_or(true) {
+            continue;
+        }
+
+        let snapshot = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|json_string| serde_json::from_str(&json_string).map_err(|err| err.to_string()));
+        match snapshot {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(err) => println!("Failed to load snapshot {}: {}", path.display(), err),
+        }
+    }
+
+    snapshots
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::models::{NewPlayer, StatusKind};
+    use crate::rating::DEFAULT_RATING;
+
+    fn game() -> Game {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = Game::new(
+            GameMode::Loop,
+            QueueType::Casual,
+            GameConfig::default().lobby,
+            vec![MapDefinition::open((16, 9))],
+            vec![(5, 5), (6, 6)],
+            SpawnHandle::default()
+        );
+        for id in 0 .. 3 {
+            game.players.push(Player::new(NewPlayer {
+                game_id: 7,
+                character_type: 1,
+                is_ai: id == 2,
+                cosmetics: Cosmetics { color: id as u8, ..Cosmetics::default() },
+                account_id: Some(id as i64),
+                rating: DEFAULT_RATING + id as f64,
+                ..NewPlayer::test(id, (id as u16, 2))
+            }, &mut rng));
+        }
+
+        game.game_started = true;
+        game.turn_index = 6;
+        game.bounds = game.bounds.shrink((4, 3));
+        game.players[0].program = vec![Some(Mutation { user_id: 0, card_type: 3, card_location: 0 })];
+        game.players[0].card_options = Some(vec![1, 2]);
+        game.players[0].deck = Some(Deck::new(vec![1, 2, 3], &mut rng));
+        game.players[1].add_status(StatusKind::Shielded, 6, 2);
+        game.players[1].stats.cards_played.insert(3, 2);
+        game.eliminate_player(2, Some(0));
+        game
+    }
+
+    #[test]
+    fn games_survive_a_round_trip() {
+        let game = game();
+        let json_string = serde_json::to_string(&GameSnapshot::from(7, &game)).unwrap();
+        let snapshot: GameSnapshot = serde_json::from_str(&json_string).unwrap();
+        assert_eq!(snapshot.game_id, 7);
+
+        let restored = snapshot.restore();
+        assert_eq!(serde_json::to_string(&GameSnapshot::from(7, &restored)).unwrap(), json_string);
+        assert!(restored.players.iter().all(|p| p.addr.is_none()));
+        assert!(restored.game_countdown_handle.is_none());
+    }
+
+    #[test]
+    fn unchanged_games_are_not_written_again() {
+        let dir = env::temp_dir().join(format!("ld47-snapshots-{}", std::process::id()));
+        let mut game = game();
+
+        let hash = save(&dir, 7, &game, None).unwrap();
+        fs::remove_file(dir.join("7.json")).unwrap();
+        assert_eq!(save(&dir, 7, &game, Some(hash)).unwrap(), hash);
+        assert!(!dir.join("7.json").exists());
+
+        game.turn_index += 1;
+        assert_ne!(save(&dir, 7, &game, Some(hash)).unwrap(), hash);
+        assert_eq!(load_all(&dir).len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
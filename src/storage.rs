@@ -0,0 +1,444 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::prelude::*;
+use rand::distributions::Alphanumeric;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::models::{CardUsage, GameOver};
+use crate::rating::DEFAULT_RATING;
+
+pub type SharedStorage = Arc<Mutex<dyn Storage + Send>>;
+
+#[derive(Debug)]
+pub enum StorageError {
+    UsernameTaken,
+    NotFound,
+    Database(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::UsernameTaken => write!(f, "Username already taken"),
+            StorageError::NotFound => write!(f, "Not found"),
+            StorageError::Database(err) => write!(f, "Database error: {}", err),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError::Database(err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Account {
+    pub id: i64,
+    pub username: String,
+    pub rating: f64,
+    #[serde(skip)]
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub username: String,
+    pub rating: f64,
+    pub matches: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchPlacement {
+    pub username: String,
+    pub is_ai: bool,
+    pub placement: usize,
+    pub stats: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchRecord {
+    pub id: i64,
+    pub game_id: usize,
+    pub queue: String,
+    pub turns: usize,
+    pub finished_at: i64,
+    pub placements: Vec<MatchPlacement>,
+}
+
+/// Persistence for player accounts and finished matches
+pub trait Storage {
+    /// Claims a username, returning the new account with its secret token
+    fn claim_username(&mut self, username: &str) -> Result<Account, StorageError>;
+    fn find_account(&self, token: &str) -> Result<Option<Account>, StorageError>;
+    /// Records a finished match, `account_ids` is indexed by player id
+    fn record_match(&mut self, game_over: &GameOver, account_ids: &[Option<i64>]) -> Result<i64, StorageError>;
+    /// Returns the most recent matches of an account first
+    fn match_history(&self, username: &str, limit: usize, offset: usize) -> Result<Vec<MatchRecord>, StorageError>;
+    fn update_ratings(&mut self, ratings: &[(i64, f64)]) -> Result<(), StorageError>;
+    /// Returns accounts that have played at least one match, highest rating first
+    fn leaderboard(&self, limit: usize, offset: usize) -> Result<Vec<LeaderboardEntry>, StorageError>;
+    /// Adds the usage of a finished game to the running totals
+    fn record_card_usage(&mut self, usage: &[CardUsage]) -> Result<(), StorageError>;
+    fn card_usage(&self) -> Result<Vec<CardUsage>, StorageError>;
+}
+
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        SqliteStorage::init(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self, StorageError> {
+        SqliteStorage::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS players (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                token TEXT NOT NULL UNIQUE,
+                rating REAL NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS matches (
+                id INTEGER PRIMARY KEY,
+                game_id INTEGER NOT NULL,
+                queue TEXT NOT NULL DEFAULT 'Casual',
+                turns INTEGER NOT NULL,
+                finished_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS placements (
+                match_id INTEGER NOT NULL REFERENCES matches(id),
+                player_id INTEGER REFERENCES players(id),
+                username TEXT NOT NULL,
+                is_ai INTEGER NOT NULL,
+                placement INTEGER NOT NULL,
+                stats TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS placements_player_id ON placements(player_id);
+            CREATE TABLE IF NOT EXISTS card_usage (
+                card INTEGER PRIMARY KEY,
+                offered INTEGER NOT NULL,
+                picked INTEGER NOT NULL,
+                winning_picks INTEGER NOT NULL
+            );
+        ")?;
+
+        // Accounts from before ratings existed start at the default
+        let has_rating = conn.prepare("SELECT 1 FROM pragma_table_info('players') WHERE name = 'rating'")?.exists(params![])?;
+        if !has_rating {
+            conn.execute(&format!("ALTER TABLE players ADD COLUMN rating REAL NOT NULL DEFAULT {}", DEFAULT_RATING), params![])?;
+        }
+
+        // Databases from before queue types existed only have casual matches
+        let has_queue = conn.prepare("SELECT 1 FROM pragma_table_info('matches') WHERE name = 'queue'")?.exists(params![])?;
+        if !has_queue {
+            conn.execute("ALTER TABLE matches ADD COLUMN queue TEXT NOT NULL DEFAULT 'Casual'", params![])?;
+        }
+
+        Ok(SqliteStorage { conn: conn })
+    }
+
+    fn now() -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+    }
+
+    fn placements(&self, match_id: i64) -> Result<Vec<MatchPlacement>, StorageError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT username, is_ai, placement, stats FROM placements WHERE match_id = ?1 ORDER BY placement"
+        )?;
+        let rows = stmt.query_map(params![match_id], |row| {
+            let stats: String = row.get(3)?;
+            Ok(MatchPlacement {
+                username: row.get(0)?,
+                is_ai: row.get(1)?,
+                placement: row.get::<_, i64>(2)? as usize,
+                stats: serde_json::from_str(&stats).unwrap_or(serde_json::Value::Null),
+            })
+        })?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn claim_username(&mut self, username: &str) -> Result<Account, StorageError> {
+        let token = thread_rng().sample_iter(&Alphanumeric).take(32).collect::<String>();
+        let taken: Option<i64> = self.conn.query_row(
+            "SELECT id FROM players WHERE username = ?1",
+            params![username],
+            |row| row.get(0),
+        ).optional()?;
+
+        if taken.is_some() {
+            return Err(StorageError::UsernameTaken);
+        }
+
+        self.conn.execute(
+            "INSERT INTO players (username, token, rating, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![username, token, DEFAULT_RATING, SqliteStorage::now()],
+        )?;
+
+        Ok(Account {
+            id: self.conn.last_insert_rowid(),
+            username: String::from(username),
+            rating: DEFAULT_RATING,
+            token: token,
+        })
+    }
+
+    fn find_account(&self, token: &str) -> Result<Option<Account>, StorageError> {
+        Ok(self.conn.query_row(
+            "SELECT id, username, rating, token FROM players WHERE token = ?1",
+            params![token],
+            |row| Ok(Account {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                rating: row.get(2)?,
+                token: row.get(3)?,
+            }),
+        ).optional()?)
+    }
+
+    fn record_match(&mut self, game_over: &GameOver, account_ids: &[Option<i64>]) -> Result<i64, StorageError> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO matches (game_id, queue, turns, finished_at) VALUES (?1, ?2, ?3, ?4)",
+            params![game_over.game_id as i64, game_over.queue.name(), game_over.turns as i64, SqliteStorage::now()],
+        )?;
+        let match_id = tx.last_insert_rowid();
+
+        for result in game_over.players.iter() {
+            let stats = serde_json::to_string(&result.stats).unwrap_or_default();
+            tx.execute(
+                "INSERT INTO placements (match_id, player_id, username, is_ai, placement, stats)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    match_id,
+                    account_ids.get(result.user_id).cloned().flatten(),
+                    result.username,
+                    result.is_ai,
+                    result.placement as i64,
+                    stats,
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(match_id)
+    }
+
+    fn match_history(&self, username: &str, limit: usize, offset: usize) -> Result<Vec<MatchRecord>, StorageError> {
+        let player_id: i64 = self.conn.query_row(
+            "SELECT id FROM players WHERE username = ?1",
+            params![username],
+            |row| row.get(0),
+        ).optional()?.ok_or(StorageError::NotFound)?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT m.id, m.game_id, m.queue, m.turns, m.finished_at FROM matches m
+             JOIN placements p ON p.match_id = m.id
+             WHERE p.player_id = ?1
+             ORDER BY m.finished_at DESC, m.id DESC
+             LIMIT ?2 OFFSET ?3"
+        )?;
+        let rows = stmt.query_map(params![player_id, limit as i64, offset as i64], |row| {
+            Ok(MatchRecord {
+                id: row.get(0)?,
+                game_id: row.get::<_, i64>(1)? as usize,
+                queue: row.get(2)?,
+                turns: row.get::<_, i64>(3)? as usize,
+                finished_at: row.get(4)?,
+                placements: Vec::new(),
+            })
+        })?;
+
+        let mut matches = rows.collect::<Result<Vec<_>, _>>()?;
+        for record in matches.iter_mut() {
+            record.placements = self.placements(record.id)?;
+        }
+
+        Ok(matches)
+    }
+
+    fn update_ratings(&mut self, ratings: &[(i64, f64)]) -> Result<(), StorageError> {
+        let tx = self.conn.transaction()?;
+        for (account_id, rating) in ratings.iter() {
+            tx.execute("UPDATE players SET rating = ?1 WHERE id = ?2", params![rating, account_id])?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn leaderboard(&self, limit: usize, offset: usize) -> Result<Vec<LeaderboardEntry>, StorageError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT pl.username, pl.rating, COUNT(p.match_id) FROM players pl
+             JOIN placements p ON p.player_id = pl.id
+             GROUP BY pl.id
+             ORDER BY pl.rating DESC, pl.id
+             LIMIT ?1 OFFSET ?2"
+        )?;
+        let rows = stmt.query_map(params![limit as i64, offset as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?, row.get::<_, i64>(2)?))
+        })?;
+
+        let mut entries = Vec::new();
+        for (i, row) in rows.enumerate() {
+            let (username, rating, matches) = row?;
+            entries.push(LeaderboardEntry {
+                rank: offset + i + 1,
+                username: username,
+                rating: rating,
+                matches: matches as usize,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn record_card_usage(&mut self, usage: &[CardUsage]) -> Result<(), StorageError> {
+        let tx = self.conn.transaction()?;
+        for card_usage in usage.iter() {
+            tx.execute(
+                "INSERT INTO card_usage (card, offered, picked, winning_picks) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(card) DO UPDATE SET
+                    offered = offered + excluded.offered,
+                    picked = picked + excluded.picked,
+                    winning_picks = winning_picks + excluded.winning_picks",
+                params![
+                    card_usage.card as i64,
+                    card_usage.offered as i64,
+                    card_usage.picked as i64,
+                    card_usage.winning_picks as i64,
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn card_usage(&self) -> Result<Vec<CardUsage>, StorageError> {
+        let mut stmt = self.conn.prepare("SELECT card, offered, picked, winning_picks FROM card_usage ORDER BY card")?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok(CardUsage {
+                card: row.get::<_, i64>(0)? as u8,
+                offered: row.get::<_, i64>(1)? as usize,
+                picked: row.get::<_, i64>(2)? as usize,
+                winning_picks: row.get::<_, i64>(3)? as usize,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QueueType;
+    use crate::models::{PlayerResult, PlayerStats};
+
+    /// A finished game where the players placed in the order given
+    fn game_over(game_id: usize, usernames: &[&str]) -> GameOver {
+        GameOver {
+            game_id: game_id,
+            queue: QueueType::Casual,
+            winner_id: Some(0),
+            winning_team: None,
+            turns: 10,
+            players: usernames.iter().enumerate().map(|(i, username)| PlayerResult {
+                user_id: i,
+                username: String::from(*username),
+                character_type: 0,
+                team: None,
+                is_ai: false,
+                placement: i + 1,
+                stats: PlayerStats::default(),
+            }).collect(),
+        }
+    }
+
+    #[test]
+    fn claimed_usernames_are_taken() {
+        let mut storage = SqliteStorage::in_memory().unwrap();
+        let account = storage.claim_username("alice").unwrap();
+
+        assert_eq!(account.rating, DEFAULT_RATING);
+        assert!(matches!(storage.claim_username("alice"), Err(StorageError::UsernameTaken)));
+        assert_eq!(storage.find_account(&account.token).unwrap().map(|a| a.id), Some(account.id));
+        assert!(storage.find_account("not a token").unwrap().is_none());
+    }
+
+    #[test]
+    fn players_without_ratings_are_migrated() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE players (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                token TEXT NOT NULL UNIQUE,
+                created_at INTEGER NOT NULL
+            );
+            INSERT INTO players (username, token, created_at) VALUES ('alice', 'token', 0);
+        ").unwrap();
+
+        let storage = SqliteStorage::init(conn).unwrap();
+        assert_eq!(storage.find_account("token").unwrap().map(|a| a.rating), Some(DEFAULT_RATING));
+    }
+
+    #[test]
+    fn match_history_is_newest_first_and_paginated() {
+        let mut storage = SqliteStorage::in_memory().unwrap();
+        let alice = storage.claim_username("alice").unwrap();
+        for game_id in 0 .. 3 {
+            storage.record_match(&game_over(game_id, &["alice", "Bot 1"]), &[Some(alice.id), None]).unwrap();
+        }
+
+        let history = storage.match_history("alice", 2, 0).unwrap();
+        assert_eq!(history.iter().map(|m| m.game_id).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(history[0].placements.len(), 2);
+        assert_eq!(history[0].placements[0].username, "alice");
+        assert_eq!(history[0].placements[1].placement, 2);
+
+        let rest = storage.match_history("alice", 2, 2).unwrap();
+        assert_eq!(rest.iter().map(|m| m.game_id).collect::<Vec<_>>(), vec![0]);
+        assert!(matches!(storage.match_history("nobody", 2, 0), Err(StorageError::NotFound)));
+    }
+
+    #[test]
+    fn leaderboard_ranks_players_with_matches_by_rating() {
+        let mut storage = SqliteStorage::in_memory().unwrap();
+        let alice = storage.claim_username("alice").unwrap();
+        let bob = storage.claim_username("bob").unwrap();
+        let carol = storage.claim_username("carol").unwrap();
+        // Never played so never ranked
+        storage.claim_username("dave").unwrap();
+
+        storage.record_match(
+            &game_over(0, &["alice", "bob", "carol"]),
+            &[Some(alice.id), Some(bob.id), Some(carol.id)]
+        ).unwrap();
+        storage.update_ratings(&[(alice.id, 1400.0), (bob.id, 1600.0), (carol.id, 1500.0)]).unwrap();
+
+        let first_page = storage.leaderboard(2, 0).unwrap();
+        assert_eq!(first_page.iter().map(|e| e.username.as_str()).collect::<Vec<_>>(), vec!["bob", "carol"]);
+        assert_eq!(first_page.iter().map(|e| e.rank).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(first_page[0].matches, 1);
+
+        let second_page = storage.leaderboard(2, 2).unwrap();
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second_page[0].username, "alice");
+        assert_eq!(second_page[0].rank, 3);
+    }
+}
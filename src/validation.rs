@@ -0,0 +1,203 @@
+use std::env;
+use std::fmt;
+use std::fs;
+
+use serde::Serialize;
+
+pub const MIN_USERNAME_LEN: usize = 2;
+pub const MAX_USERNAME_LEN: usize = 16;
+/// Names only the server may hand out, compared after normalisation
+const RESERVED_NAMES: [&str; 4] = ["admin", "server", "system", "moderator"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind")]
+pub enum UsernameError {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    InvalidCharacters,
+    Reserved,
+    Blocked,
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UsernameError::TooShort { min } => write!(f, "Username must be at least {} characters", min),
+            UsernameError::TooLong { max } => write!(f, "Username must be at most {} characters", max),
+            UsernameError::InvalidCharacters => write!(f, "Username may only contain letters, digits, spaces, '-', '_' and '.'"),
+            UsernameError::Reserved => write!(f, "Username is reserved"),
+            UsernameError::Blocked => write!(f, "Username is not allowed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub struct InvalidUsername {
+    pub error: UsernameError,
+    pub message: String,
+}
+
+impl InvalidUsername {
+    pub fn from(error: UsernameError) -> Self {
+        InvalidUsername {
+            message: error.to_string(),
+            error: error,
+        }
+    }
+}
+
+/// Lowercases a name, undoes common letter substitutions and drops everything but letters and digits
+fn normalize(username: &str) -> String {
+    username.chars()
+        .filter_map(|c| match c.to_ascii_lowercase() {
+            '0' => Some('o'),
+            '1' | '!' => Some('i'),
+            '3' => Some('e'),
+            '4' | '@' => Some('a'),
+            '5' | '$' => Some('s'),
+            '7' => Some('t'),
+            c if c.is_ascii_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether the name could be mistaken for one of the bots from `fill_slots_with_ai`
+fn looks_like_bot(username: &str) -> bool {
+    let lower = username.to_ascii_lowercase();
+    let rest = lower.trim_start_matches("bot").trim_start_matches([' ', '_', '-']);
+
+    lower.starts_with("bot") && rest.chars().all(|c| c.is_ascii_digit())
+}
+
+pub struct UsernameFilter {
+    blocked: Vec<String>,
+}
+
+impl UsernameFilter {
+    pub fn new(blocked: Vec<String>) -> Self {
+        UsernameFilter {
+            blocked: blocked.iter().map(|word| normalize(word)).filter(|word| !word.is_empty()).collect(),
+        }
+    }
+
+    /// Loads the block-list from the file in `LD47_BLOCKLIST`, one word per line
+    pub fn from_env() -> Self {
+        let blocked = match env::var("LD47_BLOCKLIST") {
+            Ok(path) => match fs::read_to_string(&path) {
+                Ok(contents) => contents.lines().map(String::from).collect(),
+                Err(err) => {
+                    println!("Failed to read block-list {}: {}", path, err);
+                    Vec::new()
+                },
+            },
+            Err(_) => Vec::new(),
+        };
+
+        UsernameFilter::new(blocked)
+    }
+
+    /// Returns the trimmed username if it is acceptable
+    pub fn validate(&self, username: &str) -> Result<String, UsernameError> {
+        let username = username.trim();
+        let len = username.chars().count();
+
+        if len < MIN_USERNAME_LEN {
+            return Err(UsernameError::TooShort { min: MIN_USERNAME_LEN });
+        }
+        if len > MAX_USERNAME_LEN {
+            return Err(UsernameError::TooLong { max: MAX_USERNAME_LEN });
+        }
+        if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_' || c == '.') {
+            return Err(UsernameError::InvalidCharacters);
+        }
+
+        let normalized = normalize(username);
+        if looks_like_bot(username) || RESERVED_NAMES.iter().any(|name| normalized == *name) {
+            return Err(UsernameError::Reserved);
+        }
+        if self.blocked.iter().any(|word| normalized.contains(word.as_str())) {
+            return Err(UsernameError::Blocked);
+        }
+
+        Ok(String::from(username))
+    }
+}
+
+/// Suffixes the username with a number if someone in `taken` already uses it, shortening
+/// the name so it still fits in `MAX_USERNAME_LEN`
+pub fn unique_username<'a, I: Iterator<Item = &'a str> + Clone>(username: String, taken: I) -> String {
+    let is_taken = |name: &str| taken.clone().any(|other| other.eq_ignore_ascii_case(name));
+
+    if !is_taken(&username) {
+        return username;
+    }
+
+    let mut n = 2;
+    loop {
+        let suffix = format!(" {}", n);
+        let base: String = username.chars().take(MAX_USERNAME_LEN.saturating_sub(suffix.len())).collect();
+        let candidate = format!("{}{}", base.trim_end(), suffix);
+        if !is_taken(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> UsernameFilter {
+        UsernameFilter::new(vec![String::from("badword")])
+    }
+
+    #[test]
+    fn rejects_names_of_the_wrong_length() {
+        assert_eq!(filter().validate(" a "), Err(UsernameError::TooShort { min: MIN_USERNAME_LEN }));
+        assert_eq!(filter().validate("abcdefghijklmnopq"), Err(UsernameError::TooLong { max: MAX_USERNAME_LEN }));
+        assert_eq!(filter().validate("abcdefghijklmnop"), Ok(String::from("abcdefghijklmnop")));
+    }
+
+    #[test]
+    fn rejects_characters_outside_the_allowed_set() {
+        for username in ["tab\tname", "emoji😀", "semi;colon", "<script>"].iter() {
+            assert_eq!(filter().validate(username), Err(UsernameError::InvalidCharacters), "{}", username);
+        }
+        assert_eq!(filter().validate("  Mr. Some_one-2 "), Ok(String::from("Mr. Some_one-2")));
+    }
+
+    #[test]
+    fn rejects_reserved_and_bot_like_names() {
+        for username in ["admin", "ADM1N", "S.y.s.t.e.m", "Bot 3", "bot_12", "BOT"].iter() {
+            assert_eq!(filter().validate(username), Err(UsernameError::Reserved), "{}", username);
+        }
+        assert!(filter().validate("Botany").is_ok());
+        assert!(filter().validate("Administrator").is_ok());
+    }
+
+    #[test]
+    fn rejects_block_listed_words_through_substitutions() {
+        for username in ["badword", "my B4dW0rd", "b.a.d.w.o.r.d"].iter() {
+            assert_eq!(filter().validate(username), Err(UsernameError::Blocked), "{}", username);
+        }
+    }
+
+    #[test]
+    fn suffixes_taken_names() {
+        let taken = ["alice", "Alice 2"];
+        assert_eq!(unique_username(String::from("bob"), taken.iter().cloned()), "bob");
+        assert_eq!(unique_username(String::from("ALICE"), taken.iter().cloned()), "ALICE 3");
+    }
+
+    #[test]
+    fn suffixed_names_stay_within_the_length_limit() {
+        let long = "abcdefghijklmnop";
+        let taken = [long, "abcdefghijklmn 2"];
+        let unique = unique_username(String::from(long), taken.iter().cloned());
+        assert_eq!(unique, "abcdefghijklmn 3");
+        assert!(unique.chars().count() <= MAX_USERNAME_LEN);
+    }
+}